@@ -1,3 +1,4 @@
 #[cfg(feature = "database")]
 pub mod sqlx;
+pub mod url;
 pub mod youtube_dl;
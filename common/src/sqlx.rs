@@ -76,6 +76,27 @@ pub struct Video {
     pub tags:       Json<Option<Vec<String>>>,
 }
 
+/// Video matching a catalog search, with the name of its channel
+#[derive(Clone, Debug, Eq, Hash, PartialEq, FromRow)]
+pub struct SearchResult {
+    pub id:           String,
+    pub title:        String,
+    pub channel_id:   String,
+    pub channel_name: Option<String>,
+    pub tags:         Json<Option<Vec<String>>>,
+}
+
+/// Video in the catalog with the name of its channel, and the reason the proxy flagged it unavailable if it did
+#[derive(Clone, Debug, Eq, Hash, PartialEq, FromRow)]
+pub struct CatalogVideo {
+    pub id:           String,
+    pub title:        String,
+    pub channel_id:   String,
+    pub channel_name: Option<String>,
+    pub tags:         Json<Option<Vec<String>>>,
+    pub unavailable:  Option<String>,
+}
+
 impl TryFrom<SingleVideo> for Video {
     type Error = ();
 
@@ -314,32 +335,171 @@ pub async fn upsert_video(
         r"
             INSERT INTO videos (id, title, tags, channel_id)
             VALUES (?, ?, ?, ?)
-            ON DUPLICATE KEY UPDATE title = VALUES(title), channel_id = VALUES(channel_id)
+            ON DUPLICATE KEY UPDATE title = VALUES(title), channel_id = VALUES(channel_id), unavailable = NULL
         "
     } else {
         r"
             INSERT INTO videos (id, title, tags, channel_id)
             VALUES (?, ?, ?, ?)
-            ON DUPLICATE KEY UPDATE title = VALUES(title), tags = VALUES(tags), channel_id = VALUES(channel_id)
+            ON DUPLICATE KEY UPDATE title = VALUES(title), tags = VALUES(tags), channel_id = VALUES(channel_id), unavailable = NULL
         "
     };
 
     sqlx::query(sql)
         .bind(&video.id)
         .bind(&video.title)
-        .bind(&json!(video.tags))
+        .bind(json!(video.tags))
         .bind(&video.channel_id)
         .execute(conn)
         .await
 }
 
+/// Flag a video as unavailable with the reason yt-dlp gave, in the nullable `videos.unavailable` column
+pub async fn set_video_unavailable(
+    conn: &mut MySqlConnection,
+    video_id: String,
+    reason: String,
+) -> Result<MySqlQueryResult, Error> {
+    sqlx::query(
+        r"
+            UPDATE videos
+            SET unavailable = ?
+            WHERE id = ?
+        ",
+    )
+    .bind(reason)
+    .bind(video_id)
+    .execute(conn)
+    .await
+}
+
+/// Add the `videos.unavailable` column to databases created before it existed, returns whether it was missing
+pub async fn add_unavailable_column(conn: &mut MySqlConnection) -> Result<bool, Error> {
+    let columns: i64 = sqlx::query_scalar(
+        r"
+            SELECT COUNT(*)
+            FROM information_schema.COLUMNS
+            WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'videos' AND COLUMN_NAME = 'unavailable'
+        ",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if columns > 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r"
+            ALTER TABLE videos
+            ADD COLUMN unavailable VARCHAR(32) NULL
+        ",
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(true)
+}
+
+pub async fn delete_unavailable_videos(
+    conn: &mut MySqlConnection,
+) -> Result<MySqlQueryResult, Error> {
+    sqlx::query(
+        r"
+            DELETE FROM videos
+            WHERE unavailable IS NOT NULL
+        ",
+    )
+    .execute(conn)
+    .await
+}
+
+/// Add the full-text indexes [`search_videos`] needs, tags are indexed through a generated text column
+pub async fn create_search_indexes(conn: &mut MySqlConnection) -> Result<(), Error> {
+    sqlx::query(
+        r"
+            ALTER TABLE videos
+            ADD COLUMN tags_text TEXT GENERATED ALWAYS AS (CAST(tags AS CHAR)) STORED,
+            ADD FULLTEXT INDEX videos_search (title, tags_text)
+        ",
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r"
+            ALTER TABLE channels
+            ADD FULLTEXT INDEX channels_search (name)
+        ",
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Playable videos matching `query` in their title, tags, or channel name, best matches first
+pub async fn search_videos(
+    conn: &mut MySqlConnection,
+    query: &str,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<SearchResult>, Error> {
+    sqlx::query_as::<_, SearchResult>(
+        r"
+            SELECT videos.id, videos.title, videos.channel_id, channels.name AS channel_name, videos.tags
+            FROM videos
+            LEFT JOIN channels ON channels.id = videos.channel_id
+            WHERE
+                videos.unavailable IS NULL
+                AND
+                (
+                    MATCH (videos.title, videos.tags_text) AGAINST (? IN NATURAL LANGUAGE MODE)
+                    OR
+                    MATCH (channels.name) AGAINST (? IN NATURAL LANGUAGE MODE)
+                )
+            ORDER BY
+                MATCH (videos.title, videos.tags_text) AGAINST (? IN NATURAL LANGUAGE MODE)
+                + MATCH (channels.name) AGAINST (? IN NATURAL LANGUAGE MODE) DESC,
+                videos.title
+            LIMIT ?
+            OFFSET ?
+        ",
+    )
+    .bind(query)
+    .bind(query)
+    .bind(query)
+    .bind(query)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(conn)
+    .await
+}
+
+pub async fn get_catalog_video(
+    conn: &mut MySqlConnection,
+    video_id: &str,
+) -> Result<Option<CatalogVideo>, Error> {
+    sqlx::query_as::<_, CatalogVideo>(
+        r"
+            SELECT videos.id, videos.title, videos.channel_id, channels.name AS channel_name, videos.tags, videos.unavailable
+            FROM videos
+            LEFT JOIN channels ON channels.id = videos.channel_id
+            WHERE videos.id = ?
+        ",
+    )
+    .bind(video_id)
+    .fetch_optional(conn)
+    .await
+}
+
 #[must_use]
 pub fn get_tags(
     tags: Option<Vec<Option<String>>>,
     default: Option<Vec<String>>,
 ) -> Json<Option<Vec<String>>> {
-    tags.map_or(
-        default.map_or(Json(None), |default| Json(Some(default))),
+    tags.map_or_else(
+        || default.map_or(Json(None), |default| Json(Some(default))),
         |tags| {
             let tags = tags.into_iter().flatten().collect();
             Json(Some(tags))
@@ -1,16 +1,43 @@
 use std::{
     env,
+    fmt::{self, Display, Formatter},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use thiserror::Error;
 use which::which;
-use youtube_dl::{download_yt_dlp, Error, Playlist, SingleVideo, YoutubeDl, YoutubeDlOutput};
+use youtube_dl::{download_yt_dlp, Error, YoutubeDl};
+pub use youtube_dl::{Format as VideoFormat, Playlist, Protocol, SingleVideo, YoutubeDlOutput};
 
 #[derive(Debug, Error)]
 pub enum YoutubeError {
     #[error("{0}")]
-    YoutubeDL(#[from] youtube_dl::Error),
+    YoutubeDL(youtube_dl::Error),
+
+    #[error("Video is private")]
+    Private,
+
+    #[error("Video has been removed")]
+    Removed,
+
+    #[error("Video is age restricted")]
+    AgeRestricted,
+
+    #[error("Video is not available in this country")]
+    GeoBlocked,
+
+    #[error("Video is only available to channel members")]
+    MembersOnly,
+
+    #[error("Live stream or premiere hasn't started yet")]
+    LiveNotStarted,
+
+    #[error("YouTube is rate limiting or asking to confirm this isn't a bot")]
+    RateLimited,
+
+    #[error("Unsupported format profile {0}")]
+    FormatProfile(String),
 
     #[error("Playlists only")]
     Playlist,
@@ -31,6 +58,270 @@ pub enum YoutubeError {
     VideoUrl,
 }
 
+impl From<youtube_dl::Error> for YoutubeError {
+    fn from(error: youtube_dl::Error) -> Self {
+        if let Error::ExitCode { stderr, .. } = &error {
+            if let Some(error) = Self::from_stderr(stderr) {
+                return error;
+            }
+        }
+
+        Self::YoutubeDL(error)
+    }
+}
+
+impl YoutubeError {
+    /// Short stable name of why a video can't be played, `None` for errors that may not happen again
+    #[must_use]
+    pub const fn unavailable_reason(&self) -> Option<&'static str> {
+        match self {
+            Self::Private => Some("private"),
+            Self::Removed => Some("removed"),
+            Self::AgeRestricted => Some("age_restricted"),
+            Self::GeoBlocked => Some("geo_blocked"),
+            Self::MembersOnly => Some("members_only"),
+            Self::LiveNotStarted => Some("live_not_started"),
+            _ => None,
+        }
+    }
+
+    /// Inverse of [`YoutubeError::unavailable_reason`]
+    #[must_use]
+    pub fn from_unavailable_reason(reason: &str) -> Option<Self> {
+        match reason {
+            "private" => Some(Self::Private),
+            "removed" => Some(Self::Removed),
+            "age_restricted" => Some(Self::AgeRestricted),
+            "geo_blocked" => Some(Self::GeoBlocked),
+            "members_only" => Some(Self::MembersOnly),
+            "live_not_started" => Some(Self::LiveNotStarted),
+            _ => None,
+        }
+    }
+
+    /// Classify the reason yt-dlp gave for failing, more specific reasons are checked first
+    /// because most of them are prefixed with a generic "Video unavailable"
+    #[must_use]
+    pub fn from_stderr(stderr: &str) -> Option<Self> {
+        let stderr = stderr.to_lowercase().replace('’', "'");
+        let contains = |patterns: &[&str]| patterns.iter().any(|pattern| stderr.contains(pattern));
+
+        if contains(&["private video", "this video is private"]) {
+            Some(Self::Private)
+        } else if contains(&["members-only", "available to this channel's members"]) {
+            Some(Self::MembersOnly)
+        } else if contains(&[
+            "confirm your age",
+            "age-restricted",
+            "inappropriate for some users",
+        ]) {
+            Some(Self::AgeRestricted)
+        } else if contains(&["in your country", "geo restriction", "geo-restricted"]) {
+            Some(Self::GeoBlocked)
+        } else if contains(&[
+            "live event will begin",
+            "premieres in",
+            "premiere will begin",
+        ]) {
+            Some(Self::LiveNotStarted)
+        } else if contains(&[
+            "not a bot",
+            "http error 429",
+            "too many requests",
+            // Throttled requests are also told the video is unavailable, but only for now
+            "content isn't available",
+            "try again later",
+        ]) {
+            Some(Self::RateLimited)
+        } else if contains(&[
+            "video unavailable",
+            "has been removed",
+            "account associated with this video has been terminated",
+            "no longer available",
+        ]) {
+            Some(Self::Removed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Resolutions clients are allowed to ask for
+pub const RESOLUTIONS: [u32; 6] = [144, 240, 360, 480, 720, 1080];
+
+/// Highest resolution of progressive formats, anything above is only available as separate DASH streams
+pub const PROGRESSIVE_MAX_RES: u32 = 720;
+
+/// Video codecs clients are allowed to ask for
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Codec {
+    H264,
+    Vp9,
+    Av1,
+}
+
+impl Codec {
+    /// Prefix of the yt-dlp `vcodec` field
+    #[must_use]
+    pub const fn vcodec(self) -> &'static str {
+        match self {
+            Self::H264 => "avc1",
+            Self::Vp9 => "vp",
+            Self::Av1 => "av01",
+        }
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::H264 => f.write_str("h264"),
+            Self::Vp9 => f.write_str("vp9"),
+            Self::Av1 => f.write_str("av1"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = YoutubeError;
+
+    fn from_str(codec: &str) -> Result<Self, Self::Err> {
+        match codec.to_lowercase().as_str() {
+            "h264" | "avc" | "avc1" => Ok(Self::H264),
+            "vp9" | "vp09" => Ok(Self::Vp9),
+            "av1" | "av01" => Ok(Self::Av1),
+            _ => Err(YoutubeError::FormatProfile(format!("codec={codec}"))),
+        }
+    }
+}
+
+/// Format profile a client asked for, the default is a progressive mp4 which every player supports
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Format {
+    /// Maximum height of the video
+    pub res:   Option<u32>,
+    pub codec: Option<Codec>,
+    /// Audio only, resolution and codec are ignored
+    pub audio: bool,
+}
+
+impl Format {
+    /// Build a profile from the `res`, `codec`, and `audio` query parameters, checked against the allowlist
+    pub fn from_query(
+        res: Option<&str>,
+        codec: Option<&str>,
+        audio: Option<&str>,
+    ) -> Result<Self, YoutubeError> {
+        let res = match res {
+            None => None,
+            Some(res) => match res.trim_end_matches('p').parse() {
+                Ok(res) if RESOLUTIONS.contains(&res) => Some(res),
+                _ => return Err(YoutubeError::FormatProfile(format!("res={res}"))),
+            },
+        };
+
+        let audio = match audio {
+            None | Some("0" | "false") => false,
+            Some("1" | "true") => true,
+            Some(audio) => return Err(YoutubeError::FormatProfile(format!("audio={audio}"))),
+        };
+
+        let codec = codec.map(str::parse).transpose()?;
+
+        // Drop what audio ignores, so every audio profile shares one cache key
+        if audio {
+            return Ok(Self {
+                audio,
+                ..Self::default()
+            });
+        }
+
+        Ok(Self { res, codec, audio })
+    }
+
+    /// Whether this profile selects separate DASH video and audio streams that need muxing
+    #[must_use]
+    pub fn is_dash(&self) -> bool {
+        !self.audio && self.res.is_some_and(|res| res > PROGRESSIVE_MAX_RES)
+    }
+
+    /// yt-dlp format selector of this profile
+    #[must_use]
+    pub fn selector(&self) -> String {
+        if self.audio {
+            return String::from("bestaudio[ext=m4a]/bestaudio[acodec^=mp4a]/bestaudio");
+        }
+
+        // https://blog.natalie.ee/posts/building-dynamic-vrchat-world/#how-vrchat-media-players-work-and-a-little-optimization
+        let res = self
+            .res
+            .map(|res| format!("[height<=?{res}]"))
+            .unwrap_or_default();
+        let codec = self
+            .codec
+            .map(|codec| format!("[vcodec^={}]", codec.vcodec()))
+            .unwrap_or_default();
+        let filters = format!("[height>=?64][width>=?64]{res}{codec}");
+
+        let any_codec = format!("[height>=?64][width>=?64]{res}");
+        let selector = if self.is_dash() {
+            // Falls back to a progressive format if there are no separate streams
+            format!(
+                "bestvideo{filters}+bestaudio[ext=m4a]/bestvideo{filters}+bestaudio/best{filters}"
+            )
+        } else {
+            format!("mp4{filters}/best{filters}")
+        };
+
+        // Videos without the codec fall back to any codec, which can be transcoded
+        let selector = match (self.codec, self.is_dash()) {
+            (None, _) => selector,
+            (Some(_), true) => format!("{selector}/bestvideo{any_codec}+bestaudio/best{any_codec}"),
+            (Some(_), false) => format!("{selector}/best{any_codec}"),
+        };
+
+        // Live streams only have HLS formats
+        format!("{selector}/best[protocol^=m3u8]")
+    }
+}
+
+/// Query string of the profile, empty for the default
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut params = Vec::new();
+        if let Some(res) = self.res {
+            params.push(format!("res={res}"));
+        }
+        if let Some(codec) = self.codec {
+            params.push(format!("codec={codec}"));
+        }
+        if self.audio {
+            params.push(String::from("audio=1"));
+        }
+
+        f.write_str(&params.join("&"))
+    }
+}
+
+/// Parse a query string of `res`, `codec`, and `audio` parameters, the inverse of [`Format`]'s `Display`
+impl FromStr for Format {
+    type Err = YoutubeError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let (mut res, mut codec, mut audio) = (None, None, None);
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("res", value)) => res = Some(value),
+                Some(("codec", value)) => codec = Some(value),
+                Some(("audio", value)) => audio = Some(value),
+                _ => return Err(YoutubeError::FormatProfile(param.to_string())),
+            }
+        }
+
+        Self::from_query(res, codec, audio)
+    }
+}
+
 pub async fn get_youtube_dl_path() -> Result<PathBuf, Error> {
     match which("yt-dlp") {
         Ok(path) => Ok(path),
@@ -38,24 +329,55 @@ pub async fn get_youtube_dl_path() -> Result<PathBuf, Error> {
     }
 }
 
+fn build<P, U>(youtube_dl_path: P, url: U, flat_playlist: bool, format: Format) -> YoutubeDl
+where
+    P: AsRef<Path>,
+    U: Into<String>,
+{
+    let mut youtube_dl = YoutubeDl::new(url);
+    youtube_dl
+        .flat_playlist(flat_playlist)
+        .format(format.selector())
+        .ignore_errors(true)
+        .socket_timeout("15")
+        // The generic extractor follows whatever a page embeds, which could be any host
+        .extra_arg("--ies")
+        .extra_arg("default,-generic")
+        .youtube_dl_path(youtube_dl_path);
+
+    youtube_dl
+}
+
 pub fn get_output<P, U>(
     youtube_dl_path: P,
     url: U,
     flat_playlist: bool,
+    format: Format,
 ) -> Result<YoutubeDlOutput, YoutubeError>
 where
     P: AsRef<Path>,
     U: Into<String>,
 {
-    // https://blog.natalie.ee/posts/building-dynamic-vrchat-world/#how-vrchat-media-players-work-and-a-little-optimization
-    YoutubeDl::new(url)
-        .flat_playlist(flat_playlist)
-        .format("mp4[height>=?64][width>=?64]/best[height>=?64][width>=?64]")
-        .ignore_errors(true)
-        .socket_timeout("15")
-        .youtube_dl_path(youtube_dl_path)
+    build(youtube_dl_path, url, flat_playlist, format)
         .run()
-        .map_err(YoutubeError::YoutubeDL)
+        .map_err(YoutubeError::from)
+}
+
+/// Same as [`get_output`] but without blocking the async runtime while yt-dlp runs
+pub async fn get_output_async<P, U>(
+    youtube_dl_path: P,
+    url: U,
+    flat_playlist: bool,
+    format: Format,
+) -> Result<YoutubeDlOutput, YoutubeError>
+where
+    P: AsRef<Path>,
+    U: Into<String>,
+{
+    build(youtube_dl_path, url, flat_playlist, format)
+        .run_async()
+        .await
+        .map_err(YoutubeError::from)
 }
 
 pub fn get_playlist<P, U>(
@@ -67,26 +389,97 @@ where
     P: AsRef<Path>,
     U: Into<String>,
 {
-    let output = get_output(youtube_dl_path, url, flat_playlist)?;
+    into_playlist(get_output(
+        youtube_dl_path,
+        url,
+        flat_playlist,
+        Format::default(),
+    )?)
+}
 
-    let YoutubeDlOutput::Playlist(playlist) = output else {
-        return Err(YoutubeError::Playlist);
-    };
+pub async fn get_playlist_async<P, U>(
+    youtube_dl_path: P,
+    url: U,
+    flat_playlist: bool,
+) -> Result<Box<Playlist>, YoutubeError>
+where
+    P: AsRef<Path>,
+    U: Into<String>,
+{
+    into_playlist(get_output_async(youtube_dl_path, url, flat_playlist, Format::default()).await?)
+}
 
-    Ok(playlist)
+/// Search `YouTube` for the first `count` videos matching `query`, without extracting their formats
+pub fn search<P>(
+    youtube_dl_path: P,
+    query: &str,
+    count: usize,
+) -> Result<Vec<SingleVideo>, YoutubeError>
+where
+    P: AsRef<Path>,
+{
+    let playlist = get_playlist(youtube_dl_path, format!("ytsearch{count}:{query}"), true)?;
+    Ok(playlist.entries.unwrap_or_default())
+}
+
+pub async fn search_async<P>(
+    youtube_dl_path: P,
+    query: &str,
+    count: usize,
+) -> Result<Vec<SingleVideo>, YoutubeError>
+where
+    P: AsRef<Path>,
+{
+    let playlist =
+        get_playlist_async(youtube_dl_path, format!("ytsearch{count}:{query}"), true).await?;
+    Ok(playlist.entries.unwrap_or_default())
 }
 
 pub fn get_single_video<P, U>(
     youtube_dl_path: P,
     url: U,
     flat_playlist: bool,
+    format: Format,
 ) -> Result<Box<SingleVideo>, YoutubeError>
 where
     P: AsRef<Path>,
     U: Into<String>,
 {
-    let output = get_output(youtube_dl_path, url, flat_playlist)?;
+    // A single video has no entries to skip, and ignoring errors would discard why yt-dlp failed
+    let output = build(youtube_dl_path, url, flat_playlist, format)
+        .ignore_errors(false)
+        .run()?;
+
+    into_single_video(output)
+}
+
+pub async fn get_single_video_async<P, U>(
+    youtube_dl_path: P,
+    url: U,
+    flat_playlist: bool,
+    format: Format,
+) -> Result<Box<SingleVideo>, YoutubeError>
+where
+    P: AsRef<Path>,
+    U: Into<String>,
+{
+    let output = build(youtube_dl_path, url, flat_playlist, format)
+        .ignore_errors(false)
+        .run_async()
+        .await?;
+
+    into_single_video(output)
+}
+
+fn into_playlist(output: YoutubeDlOutput) -> Result<Box<Playlist>, YoutubeError> {
+    let YoutubeDlOutput::Playlist(playlist) = output else {
+        return Err(YoutubeError::Playlist);
+    };
+
+    Ok(playlist)
+}
 
+fn into_single_video(output: YoutubeDlOutput) -> Result<Box<SingleVideo>, YoutubeError> {
     let YoutubeDlOutput::SingleVideo(single_video) = output else {
         return Err(YoutubeError::SingleVideo);
     };
@@ -103,12 +496,10 @@ pub fn get_format_url(single_video: &SingleVideo) -> Result<String, YoutubeError
         return Err(YoutubeError::VideoFormatString);
     };
 
-    let Some(video_format) = video_formats.iter().find(|format| {
-        format
-            .format
-            .as_ref()
-            .map_or(false, |format_string| format_string == video_format_string)
-    }) else {
+    let Some(video_format) = video_formats
+        .iter()
+        .find(|format| format.format.as_ref() == Some(video_format_string))
+    else {
         return Err(YoutubeError::VideoFormat);
     };
 
@@ -118,3 +509,199 @@ pub fn get_format_url(single_video: &SingleVideo) -> Result<String, YoutubeError
 
     Ok(video_url.to_owned())
 }
+
+/// Urls of the selected formats, two for separate DASH video and audio streams, otherwise one
+pub fn get_format_urls(single_video: &SingleVideo) -> Result<Vec<String>, YoutubeError> {
+    let Some(ref video_formats) = single_video.formats else {
+        return Err(YoutubeError::VideoFormats);
+    };
+
+    let Some(ref format_ids) = single_video.format_id else {
+        return Err(YoutubeError::VideoFormatString);
+    };
+
+    format_ids
+        .split('+')
+        .map(|format_id| {
+            let Some(video_format) = video_formats
+                .iter()
+                .find(|format| format.format_id.as_deref() == Some(format_id))
+            else {
+                return Err(YoutubeError::VideoFormat);
+            };
+
+            let Some(video_url) = &video_format.url else {
+                return Err(YoutubeError::VideoUrl);
+            };
+
+            Ok(video_url.to_owned())
+        })
+        .collect()
+}
+
+/// Whether the selected video format is encoded with `codec`, formats with an unknown codec are assumed to be
+#[must_use]
+pub fn is_codec(single_video: &SingleVideo, codec: Codec) -> bool {
+    let (Some(video_formats), Some(format_ids)) = (&single_video.formats, &single_video.format_id)
+    else {
+        return true;
+    };
+
+    format_ids
+        .split('+')
+        .filter_map(|format_id| {
+            video_formats
+                .iter()
+                .find(|format| format.format_id.as_deref() == Some(format_id))
+        })
+        .filter_map(|format| format.vcodec.as_deref())
+        .filter(|vcodec| *vcodec != "none")
+        .all(|vcodec| vcodec.starts_with(codec.vcodec()))
+}
+
+/// Resolutions from [`RESOLUTIONS`] the video has a video format for, lowest first
+#[must_use]
+pub fn get_resolutions(single_video: &SingleVideo) -> Vec<u32> {
+    let video_formats = single_video.formats.as_deref().unwrap_or_default();
+
+    RESOLUTIONS
+        .into_iter()
+        .filter(|res| {
+            video_formats.iter().any(|video_format| {
+                video_format.vcodec.as_deref() != Some("none")
+                    && video_format.height == Some(f64::from(*res))
+            })
+        })
+        .collect()
+}
+
+/// Url of the HLS manifest of a live stream, the highest resolution within the profile, preferring its codec
+pub fn get_live_url(single_video: &SingleVideo, format: Format) -> Result<String, YoutubeError> {
+    let Some(ref video_formats) = single_video.formats else {
+        return Err(YoutubeError::VideoFormats);
+    };
+
+    let max_height = f64::from(format.res.unwrap_or(u32::MAX));
+    let height = |video_format: &youtube_dl::Format| video_format.height.unwrap_or_default();
+    let is_codec = |video_format: &youtube_dl::Format| {
+        format.codec.is_some_and(|codec| {
+            video_format
+                .vcodec
+                .as_deref()
+                .is_some_and(|vcodec| vcodec.starts_with(codec.vcodec()))
+        })
+    };
+
+    let Some(video_format) = video_formats
+        .iter()
+        .filter(|video_format| {
+            matches!(
+                video_format.protocol,
+                Some(Protocol::M3U8 | Protocol::M3U8Native)
+            )
+        })
+        .filter(|video_format| height(video_format) <= max_height)
+        .max_by(|a, b| {
+            is_codec(a)
+                .cmp(&is_codec(b))
+                .then(height(a).total_cmp(&height(b)))
+        })
+    else {
+        return Err(YoutubeError::VideoFormat);
+    };
+
+    let Some(video_url) = &video_format.url else {
+        return Err(YoutubeError::VideoUrl);
+    };
+
+    Ok(video_url.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_stderr() {
+        let cases = [
+            (
+                "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video",
+                Some(YoutubeError::Private),
+            ),
+            (
+                "ERROR: [youtube] abc: Join this channel to get access to members-only content like this video, and other exclusive perks.",
+                Some(YoutubeError::MembersOnly),
+            ),
+            (
+                "ERROR: [youtube] abc: This video is available to this channel's members on level: Member (or any higher level). Join this channel to get access to the members-only content and other exclusive perks.",
+                Some(YoutubeError::MembersOnly),
+            ),
+            (
+                "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users. Use --cookies-from-browser or --cookies for the authentication.",
+                Some(YoutubeError::AgeRestricted),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. The uploader has not made this video available in your country",
+                Some(YoutubeError::GeoBlocked),
+            ),
+            (
+                "ERROR: [youtube] abc: This live event will begin in 3 hours.",
+                Some(YoutubeError::LiveNotStarted),
+            ),
+            (
+                "ERROR: [youtube] abc: Premieres in 2 days",
+                Some(YoutubeError::LiveNotStarted),
+            ),
+            (
+                "ERROR: [youtube] abc: Sign in to confirm you’re not a bot. Use --cookies-from-browser or --cookies for the authentication.",
+                Some(YoutubeError::RateLimited),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This content isn’t available, try again later.",
+                Some(YoutubeError::RateLimited),
+            ),
+            (
+                "ERROR: [youtube] abc: Unable to download API page: HTTP Error 429: Too Many Requests (caused by <HTTPError 429: Too Many Requests>)",
+                Some(YoutubeError::RateLimited),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader",
+                Some(YoutubeError::Removed),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video is no longer available because the YouTube account associated with this video has been terminated.",
+                Some(YoutubeError::Removed),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable",
+                Some(YoutubeError::Removed),
+            ),
+            (
+                "ERROR: [youtube] abc: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>",
+                None,
+            ),
+        ];
+
+        for (stderr, error) in cases {
+            assert_eq!(
+                format!("{:?}", YoutubeError::from_stderr(stderr)),
+                format!("{error:?}"),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn audio_ignores_video_profile() {
+        let format = Format::from_query(Some("1080p"), Some("av1"), Some("1")).unwrap();
+        assert_eq!(
+            format,
+            Format {
+                audio: true,
+                ..Format::default()
+            }
+        );
+
+        assert!(Format::from_query(Some("123p"), None, Some("1")).is_err());
+    }
+}
@@ -5,6 +5,9 @@ use clap::{ArgAction, Parser, ValueEnum};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use common::{
     sqlx::{
+        add_unavailable_column,
+        create_search_indexes,
+        delete_unavailable_videos,
         get_biggest_channels,
         get_channels,
         get_oldest_channels,
@@ -23,7 +26,7 @@ use common::{
         PoolConnection,
         Video,
     },
-    youtube_dl::{get_playlist, get_single_video, get_youtube_dl_path},
+    youtube_dl::{get_playlist, get_single_video, get_youtube_dl_path, Format},
 };
 use indicatif::ProgressBar;
 use manager::Entries::{Channels, Videos};
@@ -51,9 +54,18 @@ enum Mode {
     /// Generate a `ProTV` custom playlist text file
     Gen,
 
+    /// Create the full-text indexes the proxy's catalog search needs
+    Index,
+
+    /// Add the columns the proxy needs to a database created before them
+    Migrate,
+
     /// Fetch videos from channels with the oldest `update_at`
     Old,
 
+    /// Delete videos the proxy flagged as unavailable
+    Prune,
+
     /// Update channels with no playlist set
     Set,
 
@@ -119,6 +131,9 @@ async fn main() -> Result<()> {
     match args.mode {
         Mode::Add => add(pool, ytdl, args).await,
         Mode::Gen => gen(pool, ytdl, args).await,
+        Mode::Index => index(pool, ytdl, args).await,
+        Mode::Migrate => migrate(pool, ytdl, args).await,
+        Mode::Prune => prune(pool, ytdl, args).await,
         Mode::Set => set(pool, ytdl, args).await,
         Mode::Tag | Mode::Old | Mode::Few | Mode::Big => get(pool, ytdl, args).await,
     }
@@ -175,7 +190,9 @@ async fn get(pool: Pool<MySql>, ytdl: PathBuf, args: Args) -> Result<()> {
     let mut conn = pool.acquire().await?;
 
     let entries = match args.mode {
-        Mode::Add | Mode::Gen | Mode::Set => unreachable!(),
+        Mode::Add | Mode::Gen | Mode::Index | Mode::Migrate | Mode::Prune | Mode::Set => {
+            unreachable!()
+        }
         Mode::Big => Channels(get_biggest_channels(&mut conn, args.limit).await?),
         Mode::Few => Channels(get_smallest_channels(&mut conn, args.limit).await?),
         Mode::Old => Channels(get_oldest_channels(&mut conn, args.limit).await?),
@@ -201,7 +218,39 @@ async fn get(pool: Pool<MySql>, ytdl: PathBuf, args: Args) -> Result<()> {
                 };
             }
         }
-    };
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::no_effect_underscore_binding)]
+async fn index(pool: Pool<MySql>, _ytdl: PathBuf, _args: Args) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    create_search_indexes(&mut conn).await?;
+
+    println!("Created the catalog search indexes");
+
+    Ok(())
+}
+
+#[allow(clippy::no_effect_underscore_binding)]
+async fn migrate(pool: Pool<MySql>, _ytdl: PathBuf, _args: Args) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    if add_unavailable_column(&mut conn).await? {
+        println!("Added the unavailable column to videos");
+    } else {
+        println!("The database is up to date");
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::no_effect_underscore_binding)]
+async fn prune(pool: Pool<MySql>, _ytdl: PathBuf, _args: Args) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    let result = delete_unavailable_videos(&mut conn).await?;
+
+    println!("Deleted {} unavailable videos", result.rows_affected());
 
     Ok(())
 }
@@ -304,7 +353,7 @@ async fn try_update_video(
     flat_playlist: bool,
 ) -> Result<()> {
     let url = format!("https://youtube.com/watch?v={}", video.id);
-    let single_video = get_single_video(ytdl, url, flat_playlist)?;
+    let single_video = get_single_video(ytdl, url, flat_playlist, Format::default())?;
 
     video.tags = get_tags(single_video.tags, Some(vec![]));
 
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use lru::LruCache;
+use rocket::{
+    serde::json::serde_json,
+    tokio::{fs as async_fs, sync::Mutex, time},
+};
+
+use super::CacheBackend;
+use crate::CachedVideo;
+
+/// In-memory cache local to this instance, evicting the least recently used past capacity
+pub struct MemoryCache {
+    entries:     Mutex<LruCache<String, CachedVideo>>,
+    evictions:   AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl MemoryCache {
+    #[must_use]
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries:     Mutex::new(LruCache::new(capacity)),
+            evictions:   AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    /// Remove every expired entry, returning how many were removed
+    pub async fn prune(&self) -> usize {
+        let now = SystemTime::now();
+        let mut entries = self.entries.lock().await;
+        let expired = entries
+            .iter()
+            .filter(|(_, cached_video)| cached_video.exp <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in &expired {
+            entries.pop(key);
+        }
+        drop(entries);
+
+        self.expirations
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+
+        expired.len()
+    }
+
+    /// Periodically prune expired entries, so entries that are never requested again don't pile up
+    pub async fn sweep(self: Arc<Self>, interval: Duration) {
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let pruned = self.prune().await;
+            if pruned > 0 {
+                info!(
+                    "Pruned {pruned} expired videos from the cache ({} expired, {} evicted in total)",
+                    self.expirations(),
+                    self.evictions()
+                );
+            }
+        }
+    }
+
+    /// Load a snapshot written by [`CacheBackend::save`], dropping entries that expired since
+    pub fn load(path: &Path, capacity: NonZeroUsize) -> io::Result<Self> {
+        let mut cache = Self::new(capacity);
+        let json = match fs::read(path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(cache),
+            Err(error) => return Err(error),
+        };
+
+        let now = SystemTime::now();
+        let entries: HashMap<String, CachedVideo> = serde_json::from_slice(&json)?;
+        for (key, cached_video) in entries {
+            if cached_video.exp > now {
+                cache.entries.get_mut().push(key, cached_video);
+            }
+        }
+
+        Ok(cache)
+    }
+}
+
+#[rocket::async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Option<CachedVideo> {
+        self.entries.lock().await.get(key).cloned()
+    }
+
+    async fn insert(&self, key: String, cached_video: CachedVideo) {
+        let evicted = self.entries.lock().await.push(key.clone(), cached_video);
+        if evicted.is_some_and(|(evicted_key, _)| evicted_key != key) {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.lock().await.pop(key);
+    }
+
+    async fn hit(&self, key: &str) {
+        if let Some(cached_video) = self.entries.lock().await.get_mut(key) {
+            cached_video.hits += 1;
+        }
+    }
+
+    async fn size(&self) -> usize {
+        let now = SystemTime::now();
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, cached_video)| cached_video.exp > now)
+            .count()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Number of expired entries pruned by [`MemoryCache::sweep`]
+    fn expirations(&self) -> u64 {
+        self.expirations.load(Ordering::Relaxed)
+    }
+
+    async fn expiring(&self, before: SystemTime, min_hits: u64) -> Vec<String> {
+        let now = SystemTime::now();
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, cached_video)| cached_video.hits >= min_hits)
+            .filter(|(_, cached_video)| (now..before).contains(&cached_video.exp))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Snapshot the cache to `path`, through a temporary file so a crash can't leave it half written
+    async fn save(&self, path: &Path) -> io::Result<()> {
+        let entries = self
+            .entries
+            .lock()
+            .await
+            .iter()
+            .map(|(key, cached_video)| (key.clone(), cached_video.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let json = serde_json::to_vec(&entries)?;
+        let temp = path.with_extension("tmp");
+        async_fs::write(&temp, json).await?;
+        async_fs::rename(temp, path).await
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rocket::tokio::{self, time};
+
+pub use self::memory::MemoryCache;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisCache;
+use crate::{config::Config, CachedVideo};
+
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Storage for resolved stream urls keyed by video id
+#[rocket::async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CachedVideo>;
+
+    async fn insert(&self, key: String, cached_video: CachedVideo);
+
+    async fn remove(&self, key: &str);
+
+    /// Count a request served from the cache, used to find popular videos to refresh
+    async fn hit(&self, key: &str);
+
+    /// Number of unexpired entries
+    async fn size(&self) -> usize;
+
+    /// Number of entries evicted to stay within capacity, shared backends evict on their own
+    fn evictions(&self) -> u64 {
+        0
+    }
+
+    /// Number of expired entries swept, shared backends expire entries on their own
+    fn expirations(&self) -> u64 {
+        0
+    }
+
+    /// Keys of unexpired entries expiring before `before` with at least `min_hits` hits
+    async fn expiring(&self, before: SystemTime, min_hits: u64) -> Vec<String>;
+
+    /// Claim the extraction of `key` for at most `ttl`, across every instance sharing this cache.
+    /// The local single-flight already deduplicates extractions, so local backends always succeed.
+    async fn lock(&self, _key: &str, _ttl: Duration) -> bool {
+        true
+    }
+
+    /// Release a claim taken with [`CacheBackend::lock`]
+    async fn unlock(&self, _key: &str) {}
+
+    /// Snapshot the cache to `path`, only local backends need to do anything
+    async fn save(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub enum Claim {
+    /// This instance holds the lock and must extract the video, then unlock it
+    Locked,
+
+    /// Another instance held the lock and cached the video
+    Cached(CachedVideo),
+}
+
+/// Claim the extraction of `key`, or wait up to `timeout` for the instance that claimed it to cache it
+pub async fn claim(cache: &dyn CacheBackend, key: &str, timeout: Duration) -> Option<Claim> {
+    let claim = async {
+        loop {
+            if cache.lock(key, timeout).await {
+                return Claim::Locked;
+            }
+
+            time::sleep(LOCK_POLL_INTERVAL).await;
+            if let Some(cached_video) = cache.get(key).await {
+                return Claim::Cached(cached_video);
+            }
+        }
+    };
+
+    time::timeout(timeout, claim).await.ok()
+}
+
+/// Connect to the shared cache if configured, otherwise load the in-memory cache and start sweeping it
+#[cfg_attr(not(feature = "redis"), allow(clippy::unused_async))]
+pub async fn from_config(config: &Config) -> Arc<dyn CacheBackend> {
+    #[cfg(feature = "redis")]
+    if let Some(url) = &config.redis_url {
+        let cache = RedisCache::connect(url)
+            .await
+            .expect("Unable to connect to redis");
+        return Arc::new(cache);
+    }
+
+    let cache = config.cache_path.as_ref().map_or_else(
+        || MemoryCache::new(config.cache_capacity),
+        |path| {
+            MemoryCache::load(path, config.cache_capacity).unwrap_or_else(|error| {
+                warn!("Unable to load cache from {}: {error}", path.display());
+                MemoryCache::new(config.cache_capacity)
+            })
+        },
+    );
+
+    let cache = Arc::new(cache);
+    if config.cache_sweep_interval > 0 {
+        let sweep_interval = Duration::from_secs(config.cache_sweep_interval);
+        tokio::spawn(cache.clone().sweep(sweep_interval));
+    }
+
+    cache
+}
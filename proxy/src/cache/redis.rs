@@ -0,0 +1,270 @@
+use std::{
+    collections::HashSet,
+    process,
+    time::{Duration, SystemTime},
+};
+
+use ::redis::{
+    aio::ConnectionManager,
+    pipe,
+    AsyncCommands,
+    Client,
+    ExistenceCheck,
+    RedisResult,
+    Script,
+    SetExpiry,
+    SetOptions,
+};
+use rocket::serde::json::serde_json;
+
+use super::CacheBackend;
+use crate::CachedVideo;
+
+const KEY_PREFIX: &str = "vrc-yt";
+const EXPIRY_KEY: &str = "vrc-yt:expiry";
+const HITS_KEY: &str = "vrc-yt:hits";
+
+/// Only delete the lock if this instance still holds it, it may have expired and been claimed by another
+const UNLOCK_SCRIPT: &str = r#"
+    if redis.call("GET", KEYS[1]) == ARGV[1] then
+        return redis.call("DEL", KEYS[1])
+    else
+        return 0
+    end
+"#;
+
+/// Redis (or any RESP compatible server) cache shared between every instance pointing at it
+pub struct RedisCache {
+    connection: ConnectionManager,
+    token:      String,
+}
+
+impl RedisCache {
+    pub async fn connect(url: &str) -> RedisResult<Self> {
+        let client = Client::open(url)?;
+        let connection = ConnectionManager::new(client).await?;
+        let nanos = SystemTime::UNIX_EPOCH
+            .elapsed()
+            .unwrap_or_default()
+            .as_nanos();
+
+        Ok(Self {
+            connection,
+            token: format!("{}-{nanos}", process::id()),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl CacheBackend for RedisCache {
+    async fn get(&self, key: &str) -> Option<CachedVideo> {
+        let mut connection = self.connection.clone();
+        let json: Option<String> = match connection.get(format!("{KEY_PREFIX}:video:{key}")).await {
+            Ok(json) => json,
+            Err(error) => {
+                warn!("Unable to get {key} from redis: {error}");
+                return None;
+            }
+        };
+
+        serde_json::from_str(&json?).ok()
+    }
+
+    async fn insert(&self, key: String, cached_video: CachedVideo) {
+        // Redis expires the entry itself, so there's nothing to sweep
+        let Ok(ttl) = cached_video.exp.duration_since(SystemTime::now()) else {
+            return;
+        };
+
+        let Ok(json) = serde_json::to_string(&cached_video) else {
+            return;
+        };
+
+        // The expiry is also kept in a sorted set, to find videos to refresh and count the cache,
+        // and a fresh entry starts without hits like it does in memory
+        let mut connection = self.connection.clone();
+        let options =
+            SetOptions::default().with_expiration(SetExpiry::PX(ttl.as_millis() as usize));
+        let result: RedisResult<()> = pipe()
+            .atomic()
+            .set_options(format!("{KEY_PREFIX}:video:{key}"), json, options)
+            .zadd(EXPIRY_KEY, &key, unix_secs(cached_video.exp))
+            .zrem(HITS_KEY, &key)
+            .query_async(&mut connection)
+            .await;
+
+        if let Err(error) = result {
+            warn!("Unable to insert {key} into redis: {error}");
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        let mut connection = self.connection.clone();
+        let result: RedisResult<()> = pipe()
+            .atomic()
+            .del(format!("{KEY_PREFIX}:video:{key}"))
+            .zrem(EXPIRY_KEY, key)
+            .zrem(HITS_KEY, key)
+            .query_async(&mut connection)
+            .await;
+
+        if let Err(error) = result {
+            warn!("Unable to remove {key} from redis: {error}");
+        }
+    }
+
+    async fn hit(&self, key: &str) {
+        let mut connection = self.connection.clone();
+        let result: RedisResult<()> = connection.zincr(HITS_KEY, key, 1).await;
+        if let Err(error) = result {
+            warn!("Unable to count hit of {key} in redis: {error}");
+        }
+    }
+
+    async fn size(&self) -> usize {
+        let mut connection = self.connection.clone();
+        let now = unix_secs(SystemTime::now());
+        let result: RedisResult<usize> = connection
+            .zcount(EXPIRY_KEY, format!("({now}"), "+inf")
+            .await;
+
+        result.unwrap_or_else(|error| {
+            warn!("Unable to count videos in redis: {error}");
+            0
+        })
+    }
+
+    async fn expiring(&self, before: SystemTime, min_hits: u64) -> Vec<String> {
+        let mut connection = self.connection.clone();
+        let now = unix_secs(SystemTime::now());
+        let before = unix_secs(before);
+
+        // The entries expire by themselves, but their expiry and hits are kept in sorted sets
+        let result: RedisResult<(Vec<String>, Vec<String>)> = pipe()
+            .zrangebyscore(EXPIRY_KEY, format!("({now}"), before)
+            .zrangebyscore(HITS_KEY, min_hits, "+inf")
+            .query_async(&mut connection)
+            .await;
+
+        let (expiring, popular) = match result {
+            Ok(keys) => keys,
+            Err(error) => {
+                warn!("Unable to get expiring videos from redis: {error}");
+                return Vec::new();
+            }
+        };
+
+        let expired: Vec<String> = connection
+            .zrangebyscore(EXPIRY_KEY, "-inf", now)
+            .await
+            .unwrap_or_default();
+
+        if !expired.is_empty() {
+            let result: RedisResult<()> = pipe()
+                .atomic()
+                .zrem(EXPIRY_KEY, &expired)
+                .zrem(HITS_KEY, &expired)
+                .query_async(&mut connection)
+                .await;
+
+            if let Err(error) = result {
+                warn!("Unable to forget expired videos in redis: {error}");
+            }
+        }
+
+        let popular = popular.into_iter().collect::<HashSet<_>>();
+        expiring
+            .into_iter()
+            .filter(|key| popular.contains(key))
+            .collect()
+    }
+
+    async fn lock(&self, key: &str, ttl: Duration) -> bool {
+        let mut connection = self.connection.clone();
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(ttl.as_millis() as usize));
+
+        let result: RedisResult<Option<String>> = connection
+            .set_options(format!("{KEY_PREFIX}:lock:{key}"), &self.token, options)
+            .await;
+
+        match result {
+            Ok(locked) => locked.is_some(),
+            Err(error) => {
+                // Extracting twice is better than not extracting at all
+                warn!("Unable to lock {key} in redis: {error}");
+                true
+            }
+        }
+    }
+
+    async fn unlock(&self, key: &str) {
+        let mut connection = self.connection.clone();
+        let result: RedisResult<()> = Script::new(UNLOCK_SCRIPT)
+            .key(format!("{KEY_PREFIX}:lock:{key}"))
+            .arg(&self.token)
+            .invoke_async(&mut connection)
+            .await;
+
+        if let Err(error) = result {
+            warn!("Unable to unlock {key} in redis: {error}");
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Run with `cargo test -p proxy --features redis -- --ignored`, against `REDIS_URL` or localhost
+    #[rocket::async_test]
+    #[ignore = "needs a local redis-server"]
+    async fn tracks_expiry() {
+        let url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        let cache = RedisCache::connect(&url).await.unwrap();
+        let key = format!("test-{}", cache.token);
+        let exp = SystemTime::now() + Duration::from_secs(90);
+        let cached_video = CachedVideo {
+            exp,
+            url: "https://example.com/video.mp4".to_string(),
+            hits: 0,
+            audio_url: None,
+            transcode: false,
+            live: false,
+            thumbnail: None,
+            unavailable: None,
+        };
+
+        let size = cache.size().await;
+        cache.insert(key.clone(), cached_video.clone()).await;
+        assert_eq!(cache.size().await, size + 1);
+        assert!(cache.get(&key).await.is_some());
+
+        cache.hit(&key).await;
+        let expiring = cache.expiring(exp + Duration::from_secs(1), 1).await;
+        assert!(expiring.contains(&key));
+        assert!(cache
+            .expiring(exp + Duration::from_secs(1), 2)
+            .await
+            .is_empty());
+
+        cache.insert(key.clone(), cached_video).await;
+        assert!(!cache
+            .expiring(exp + Duration::from_secs(1), 1)
+            .await
+            .contains(&key));
+
+        cache.remove(&key).await;
+        assert_eq!(cache.size().await, size);
+        assert!(cache.get(&key).await.is_none());
+    }
+}
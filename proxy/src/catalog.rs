@@ -0,0 +1,33 @@
+use std::convert::Infallible;
+
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request,
+};
+#[cfg(feature = "database")]
+use rocket_db_pools::Connection;
+
+#[cfg(feature = "database")]
+use crate::VRChatYouTube;
+
+/// Connection to the catalog database if it's enabled and reachable, for routes that work without it
+pub struct Catalog {
+    #[cfg(feature = "database")]
+    pub conn: Option<Connection<VRChatYouTube>>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Catalog {
+    type Error = Infallible;
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self {
+            #[cfg(feature = "database")]
+            conn:                              req
+                .guard::<Connection<VRChatYouTube>>()
+                .await
+                .succeeded(),
+        })
+    }
+}
@@ -0,0 +1,236 @@
+use std::{num::NonZeroUsize, path::PathBuf};
+
+use common::youtube_dl::{Codec, Format, YoutubeError};
+use rocket::serde::{de::Error, Deserialize, Deserializer};
+
+use crate::{platform::Platform, stream::StreamMode};
+
+/// Proxy settings, read from `Rocket.toml` or `ROCKET_` prefixed environment variables
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    /// Maximum number of videos kept in the cache before the least recently used are evicted
+    pub cache_capacity: NonZeroUsize,
+
+    /// File the cache is saved to on shutdown and loaded from on launch, disabled if unset
+    pub cache_path: Option<PathBuf>,
+
+    /// Seconds between sweeps of expired videos from the cache, 0 disables sweeping
+    pub cache_sweep_interval: u64,
+
+    /// Seconds to wait for another request already processing the same video
+    pub flight_timeout: u64,
+
+    /// Maximum number of yt-dlp processes running at once
+    pub max_extractions: usize,
+
+    /// Maximum number of requests waiting for a yt-dlp process before responding 503
+    pub extraction_queue: usize,
+
+    /// Maximum number of ffmpeg processes transcoding at once, 0 disables transcoding
+    pub max_transcodes: usize,
+
+    /// Seconds live stream manifests are cached for
+    pub live_ttl: u64,
+
+    /// Seconds before a url's own expiry that it's dropped from the cache
+    pub expiry_margin: u64,
+
+    /// Seconds urls without an expiry are cached for
+    pub fallback_ttl: u64,
+
+    /// Mux separate video and audio streams with ffmpeg for resolutions above 720p
+    pub mux: bool,
+
+    /// Maximum number of ffmpeg processes muxing at once
+    pub max_muxes: usize,
+
+    /// Seconds unavailable videos are cached for, per reason
+    pub negative_ttl: NegativeTtl,
+
+    /// Format profile used per platform when the request doesn't choose one
+    pub platform_formats: PlatformFormats,
+
+    /// Seconds between checks for popular videos to refresh, 0 disables refreshing
+    pub refresh_interval: u64,
+
+    /// Minimum number of cache hits for a video to be refreshed before it expires
+    pub refresh_min_hits: u64,
+
+    /// Seconds before expiring that a popular video is refreshed
+    pub refresh_window: u64,
+
+    /// Redis url of a cache shared between instances, the in-memory cache is used if unset
+    #[cfg(feature = "redis")]
+    pub redis_url: Option<String>,
+
+    /// Seconds sent in the `Retry-After` header when the extraction queue is full
+    pub retry_after: u64,
+
+    /// Whether to redirect to or stream through the resolved url
+    pub stream_mode: StreamMode,
+
+    /// Seconds playlists are cached for
+    pub playlist_ttl: u64,
+
+    /// Url of the proxy used in generated playlists, `https://` and the `Host` header if unset
+    pub public_url: Option<String>,
+
+    /// Hosts of other sites yt-dlp may extract from, including their subdomains
+    pub generic_hosts: Vec<String>,
+
+    /// Maximum number of characters in a search query
+    pub search_max_length: usize,
+
+    /// Longest video in seconds a search redirects to, 0 disables the cap
+    pub search_max_duration: u32,
+
+    /// Seconds search results are cached for
+    pub search_ttl: u64,
+
+    /// Number of results per page of catalog searches
+    pub catalog_page_size: u32,
+
+    /// Seconds video metadata is cached for
+    pub metadata_ttl: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cache_capacity: NonZeroUsize::new(10_000).unwrap(),
+            cache_path: None,
+            cache_sweep_interval: 60,
+            flight_timeout: 60,
+            max_extractions: 8,
+            extraction_queue: 32,
+            max_transcodes: 2,
+            live_ttl: 60,
+            expiry_margin: 60,
+            fallback_ttl: 600,
+            mux: false,
+            max_muxes: 4,
+            negative_ttl: NegativeTtl::default(),
+            platform_formats: PlatformFormats::default(),
+            refresh_interval: 60,
+            refresh_min_hits: 5,
+            refresh_window: 300,
+            #[cfg(feature = "redis")]
+            redis_url: None,
+            retry_after: 5,
+            stream_mode: StreamMode::default(),
+            playlist_ttl: 3600,
+            public_url: None,
+            generic_hosts: [
+                "twitch.tv",
+                "vimeo.com",
+                "soundcloud.com",
+                "bilibili.com",
+                "nicovideo.jp",
+            ]
+            .map(String::from)
+            .to_vec(),
+            search_max_length: 100,
+            search_max_duration: 1800,
+            search_ttl: 86400,
+            catalog_page_size: 25,
+            metadata_ttl: 3600,
+        }
+    }
+}
+
+/// Seconds unavailable videos are cached for, so replays fail fast instead of running yt-dlp again
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct NegativeTtl {
+    pub private:          u64,
+    pub removed:          u64,
+    pub age_restricted:   u64,
+    pub geo_blocked:      u64,
+    pub members_only:     u64,
+    pub live_not_started: u64,
+}
+
+impl Default for NegativeTtl {
+    fn default() -> Self {
+        Self {
+            private:          3600,
+            removed:          86400,
+            age_restricted:   86400,
+            geo_blocked:      86400,
+            members_only:     3600,
+            live_not_started: 60,
+        }
+    }
+}
+
+impl NegativeTtl {
+    /// Seconds to cache `error` for, `None` if it shouldn't be cached
+    #[must_use]
+    pub const fn get(&self, error: &YoutubeError) -> Option<u64> {
+        let ttl = match error {
+            YoutubeError::Private => self.private,
+            YoutubeError::Removed => self.removed,
+            YoutubeError::AgeRestricted => self.age_restricted,
+            YoutubeError::GeoBlocked => self.geo_blocked,
+            YoutubeError::MembersOnly => self.members_only,
+            YoutubeError::LiveNotStarted => self.live_not_started,
+            _ => return None,
+        };
+
+        if ttl > 0 {
+            Some(ttl)
+        } else {
+            None
+        }
+    }
+}
+
+/// Format profiles per platform, in the same query string form as the `res`, `codec`, and `audio` parameters
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PlatformFormats {
+    #[serde(deserialize_with = "deserialize_format")]
+    pub quest: Format,
+    #[serde(deserialize_with = "deserialize_format")]
+    pub pc:    Format,
+    #[serde(deserialize_with = "deserialize_format")]
+    pub other: Format,
+}
+
+impl Default for PlatformFormats {
+    fn default() -> Self {
+        Self {
+            // AVPro on Android only reliably plays H.264 in a progressive mp4
+            quest: Format {
+                codec: Some(Codec::H264),
+                ..Format::default()
+            },
+            pc:    Format {
+                res: Some(1080),
+                ..Format::default()
+            },
+            other: Format::default(),
+        }
+    }
+}
+
+impl PlatformFormats {
+    #[must_use]
+    pub const fn get(&self, platform: Platform) -> Format {
+        match platform {
+            Platform::Quest => self.quest,
+            Platform::Pc => self.pc,
+            Platform::Other => self.other,
+        }
+    }
+}
+
+fn deserialize_format<'de, D>(deserializer: D) -> Result<Format, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
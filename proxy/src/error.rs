@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use common::youtube_dl::YoutubeError;
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    Request,
+};
+use thiserror::Error;
+
+use crate::flight::FlightError;
+
+#[derive(Clone, Debug, Error)]
+pub enum ProxyError {
+    #[error("Unable to parse a video link from request uri")]
+    Link,
+
+    #[error("Link doesn't point at a video")]
+    VideoId,
+
+    #[error("Invalid playlist id")]
+    PlaylistId,
+
+    #[error("Unsupported playlist format {0}, expected m3u or protv")]
+    PlaylistFormat(String),
+
+    #[error("Search query must be 1 to {0} characters")]
+    SearchQuery(usize),
+
+    #[error("No videos found for the search")]
+    NoResults,
+
+    #[error("Unsupported search format {0}, expected json or protv")]
+    SearchFormat(String),
+
+    #[error("Unable to search the catalog")]
+    Catalog,
+
+    #[error("Unable to proxy video with yt-dlp")]
+    YoutubeDL,
+
+    #[error("{0}")]
+    Youtube(Arc<YoutubeError>),
+
+    #[error("Too many videos are being processed, please try again")]
+    Busy(u64),
+
+    #[error("Video processing was abandoned, please try again")]
+    Abandoned,
+
+    #[error("Timed out waiting for video to be processed")]
+    Timeout,
+
+    #[error("Unable to stream video from upstream")]
+    Upstream,
+
+    #[error("Unable to process video with ffmpeg")]
+    Ffmpeg,
+}
+
+impl From<FlightError> for ProxyError {
+    fn from(error: FlightError) -> Self {
+        match error {
+            FlightError::Abandoned => Self::Abandoned,
+            FlightError::Timeout => Self::Timeout,
+        }
+    }
+}
+
+impl From<YoutubeError> for ProxyError {
+    fn from(error: YoutubeError) -> Self {
+        match error {
+            // Unclassified errors contain the raw yt-dlp output which isn't meant for viewers
+            YoutubeError::YoutubeDL(_) => Self::YoutubeDL,
+            error => Self::Youtube(Arc::new(error)),
+        }
+    }
+}
+
+impl ProxyError {
+    #[must_use]
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Link | Self::VideoId | Self::PlaylistId | Self::NoResults => Status::NotFound,
+            Self::PlaylistFormat(_) | Self::SearchQuery(_) | Self::SearchFormat(_) => {
+                Status::BadRequest
+            }
+            Self::YoutubeDL | Self::Upstream => Status::BadGateway,
+            Self::Ffmpeg | Self::Catalog => Status::InternalServerError,
+            Self::Youtube(error) => match **error {
+                YoutubeError::YoutubeDL(_) => Status::BadGateway,
+                YoutubeError::Private | YoutubeError::AgeRestricted | YoutubeError::MembersOnly => {
+                    Status::Forbidden
+                }
+                YoutubeError::Removed => Status::Gone,
+                YoutubeError::GeoBlocked => Status::UnavailableForLegalReasons,
+                YoutubeError::LiveNotStarted => Status::new(425), // Too Early
+                YoutubeError::FormatProfile(_) => Status::BadRequest,
+                YoutubeError::RateLimited => Status::TooManyRequests,
+                YoutubeError::Playlist
+                | YoutubeError::SingleVideo
+                | YoutubeError::VideoFormats
+                | YoutubeError::VideoFormatString
+                | YoutubeError::VideoFormat
+                | YoutubeError::VideoUrl => Status::NotFound,
+            },
+            Self::Abandoned | Self::Busy(_) => Status::ServiceUnavailable,
+            Self::Timeout => Status::GatewayTimeout,
+        }
+    }
+
+    /// Label of the failure in metrics, unavailable videos are labeled with their reason
+    #[must_use]
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::VideoId => "video_id",
+            Self::PlaylistId => "playlist_id",
+            Self::PlaylistFormat(_) => "playlist_format",
+            Self::SearchQuery(_) => "search_query",
+            Self::NoResults => "no_results",
+            Self::SearchFormat(_) => "search_format",
+            Self::Catalog => "catalog",
+            Self::YoutubeDL => "yt_dlp",
+            Self::Youtube(error) => youtube_class(error),
+            Self::Busy(_) => "busy",
+            Self::Abandoned => "abandoned",
+            Self::Timeout => "timeout",
+            Self::Upstream => "upstream",
+            Self::Ffmpeg => "ffmpeg",
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ProxyError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = (self.status(), self.to_string()).respond_to(req)?;
+        if let Self::Busy(retry_after) = self {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Label of a yt-dlp failure in metrics, see [`ProxyError::class`]
+#[must_use]
+pub fn youtube_class(error: &YoutubeError) -> &'static str {
+    match error {
+        YoutubeError::YoutubeDL(_) => "yt_dlp",
+        YoutubeError::RateLimited => "rate_limited",
+        YoutubeError::FormatProfile(_) => "format_profile",
+        error => error.unavailable_reason().unwrap_or("format"),
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+};
+
+use rocket::{
+    http::{ContentType, Status},
+    response::{self, Responder},
+    tokio::{
+        io::{AsyncRead, ReadBuf},
+        process::{Child, ChildStdout, Command},
+        sync::OwnedSemaphorePermit,
+    },
+    Request,
+    Response,
+};
+
+/// Output of an ffmpeg process streamed to the client, the process is killed when the client disconnects
+pub struct FfmpegStream {
+    // Kept so the process is killed and the permit released on drop
+    _child:  Child,
+    _permit: Option<OwnedSemaphorePermit>,
+    stdout:  ChildStdout,
+}
+
+impl FfmpegStream {
+    fn spawn(
+        ffmpeg_path: &Path,
+        args: &[&str],
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> io::Result<Self> {
+        let mut child = Command::new(ffmpeg_path)
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let Some(stdout) = child.stdout.take() else {
+            return Err(io::Error::other("ffmpeg stdout wasn't piped"));
+        };
+
+        Ok(Self {
+            _child: child,
+            _permit: permit,
+            stdout,
+        })
+    }
+}
+
+impl AsyncRead for FfmpegStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for FfmpegStream {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        // Responses from the catcher default to its 404 status
+        Response::build()
+            .status(Status::Ok)
+            .header(ContentType::MP4)
+            .streamed_body(self)
+            .ok()
+    }
+}
+
+/// Mux separate video and audio streams into a fragmented mp4, which can be played while it's written,
+/// holding `permit` until the client disconnects
+pub fn mux(
+    ffmpeg_path: &Path,
+    video_url: &str,
+    audio_url: &str,
+    permit: OwnedSemaphorePermit,
+) -> io::Result<FfmpegStream> {
+    FfmpegStream::spawn(
+        ffmpeg_path,
+        &[
+            "-i",
+            video_url,
+            "-i",
+            audio_url,
+            "-map",
+            "0:v:0",
+            "-map",
+            "1:a:0",
+            "-c",
+            "copy",
+            "-movflags",
+            "frag_keyframe+empty_moov+default_base_moof",
+            "-f",
+            "mp4",
+            "pipe:1",
+        ],
+        Some(permit),
+    )
+}
+
+/// Transcode to H.264 and AAC in a fragmented mp4 for players that can't decode VP9 or AV1,
+/// holding `permit` until the client disconnects
+pub fn transcode(
+    ffmpeg_path: &Path,
+    video_url: &str,
+    audio_url: Option<&str>,
+    permit: OwnedSemaphorePermit,
+) -> io::Result<FfmpegStream> {
+    let mut args = vec!["-i", video_url];
+    if let Some(audio_url) = audio_url {
+        args.extend(["-i", audio_url, "-map", "0:v:0", "-map", "1:a:0"]);
+    }
+
+    args.extend([
+        "-c:v",
+        "libx264",
+        "-preset",
+        "veryfast",
+        "-pix_fmt",
+        "yuv420p",
+        "-c:a",
+        "aac",
+        "-movflags",
+        "frag_keyframe+empty_moov+default_base_moof",
+        "-f",
+        "mp4",
+        "pipe:1",
+    ]);
+
+    FfmpegStream::spawn(ffmpeg_path, &args, Some(permit))
+}
+
+/// Mux audio with a thumbnail as a still frame, for video players that can't play audio alone,
+/// holding `permit` until the client disconnects
+pub fn cover(
+    ffmpeg_path: &Path,
+    thumbnail_url: &str,
+    audio_url: &str,
+    permit: OwnedSemaphorePermit,
+) -> io::Result<FfmpegStream> {
+    FfmpegStream::spawn(
+        ffmpeg_path,
+        &[
+            "-loop",
+            "1",
+            "-framerate",
+            "1",
+            "-i",
+            thumbnail_url,
+            "-i",
+            audio_url,
+            "-map",
+            "0:v:0",
+            "-map",
+            "1:a:0",
+            "-vf",
+            "scale=trunc(iw/2)*2:trunc(ih/2)*2",
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-tune",
+            "stillimage",
+            "-pix_fmt",
+            "yuv420p",
+            "-c:a",
+            "aac",
+            "-shortest",
+            "-movflags",
+            "frag_keyframe+empty_moov+default_base_moof",
+            "-f",
+            "mp4",
+            "pipe:1",
+        ],
+        Some(permit),
+    )
+}
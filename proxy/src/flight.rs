@@ -0,0 +1,206 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    sync::Mutex,
+    time::Duration,
+};
+
+use rocket::tokio::{sync::watch, time};
+
+type Flight<T, E> = watch::Receiver<Option<Result<T, E>>>;
+
+enum Role<T, E> {
+    Leader(watch::Sender<Option<Result<T, E>>>),
+    Follower(Flight<T, E>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FlightError {
+    /// The leader dropped before producing a result
+    Abandoned,
+
+    /// The leader didn't produce a result within the timeout
+    Timeout,
+}
+
+/// Deduplicates concurrent work by key, the first caller leads and the rest follow
+pub struct SingleFlight<T, E> {
+    flights: Mutex<HashMap<String, Flight<T, E>>>,
+}
+
+impl<T, E> Default for SingleFlight<T, E> {
+    fn default() -> Self {
+        Self {
+            flights: Mutex::default(),
+        }
+    }
+}
+
+impl<T, E> SingleFlight<T, E>
+where
+    T: Clone + Send + Sync,
+    E: Clone + Send + Sync + From<FlightError>,
+{
+    /// Run `work` if no other caller is working on `key`, otherwise wait up to `timeout` for their result.
+    /// `work` is only polled by the leader, followers drop it without running it.
+    pub async fn run<F>(&self, key: &str, timeout: Duration, work: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>> + Send,
+    {
+        match self.join(key) {
+            Role::Leader(tx) => {
+                let _guard = FlightGuard {
+                    flights: &self.flights,
+                    key,
+                };
+
+                let result = work.await;
+                tx.send_replace(Some(result.clone()));
+                result
+            }
+            Role::Follower(mut rx) => {
+                match time::timeout(timeout, rx.wait_for(Option::is_some)).await {
+                    Ok(Ok(result)) => result.clone().unwrap(),
+                    Ok(Err(_)) => Err(FlightError::Abandoned.into()),
+                    Err(_) => Err(FlightError::Timeout.into()),
+                }
+            }
+        }
+    }
+
+    /// Start a new flight for `key`, or follow the flight already in progress
+    fn join(&self, key: &str) -> Role<T, E> {
+        match self.flights.lock().unwrap().entry(key.to_owned()) {
+            Entry::Occupied(entry) => Role::Follower(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = watch::channel(None);
+                entry.insert(rx);
+                Role::Leader(tx)
+            }
+        }
+    }
+}
+
+/// Removes the flight when the leader finishes or is dropped, so followers never wait on a dead leader
+struct FlightGuard<'a, T, E> {
+    flights: &'a Mutex<HashMap<String, Flight<T, E>>>,
+    key:     &'a str,
+}
+
+impl<T, E> Drop for FlightGuard<'_, T, E> {
+    fn drop(&mut self) {
+        if let Ok(mut flights) = self.flights.lock() {
+            flights.remove(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use rocket::tokio::{join, sync::oneshot};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum TestError {
+        Abandoned,
+        Timeout,
+        Failed,
+    }
+
+    impl From<FlightError> for TestError {
+        fn from(error: FlightError) -> Self {
+            match error {
+                FlightError::Abandoned => Self::Abandoned,
+                FlightError::Timeout => Self::Timeout,
+            }
+        }
+    }
+
+    fn is_empty<T, E>(flight: &SingleFlight<T, E>) -> bool {
+        flight.flights.lock().unwrap().is_empty()
+    }
+
+    #[rocket::async_test]
+    async fn followers_share_result() {
+        let flight = SingleFlight::<u32, TestError>::default();
+        let (tx, rx) = oneshot::channel();
+
+        let (leader, follower, ()) = join!(
+            flight.run("key", TIMEOUT, async {
+                rx.await.unwrap();
+                Ok(1)
+            }),
+            flight.run("key", TIMEOUT, async { Ok(2) }),
+            async { tx.send(()).unwrap() },
+        );
+
+        assert_eq!(leader, Ok(1));
+        assert_eq!(follower, Ok(1));
+        assert!(is_empty(&flight));
+    }
+
+    #[rocket::async_test]
+    async fn followers_share_error() {
+        let flight = SingleFlight::<u32, TestError>::default();
+        let (tx, rx) = oneshot::channel();
+
+        let (leader, follower, ()) = join!(
+            flight.run("key", TIMEOUT, async {
+                rx.await.unwrap();
+                Err(TestError::Failed)
+            }),
+            flight.run("key", TIMEOUT, async { Ok(2) }),
+            async { tx.send(()).unwrap() },
+        );
+
+        assert_eq!(leader, Err(TestError::Failed));
+        assert_eq!(follower, Err(TestError::Failed));
+        assert!(is_empty(&flight));
+    }
+
+    #[rocket::async_test]
+    async fn dropped_leader_abandons() {
+        let flight = SingleFlight::<u32, TestError>::default();
+
+        let (leader, follower) = join!(
+            time::timeout(
+                Duration::from_millis(50),
+                flight.run("key", TIMEOUT, future::pending()),
+            ),
+            flight.run("key", TIMEOUT, async { Ok(2) }),
+        );
+
+        assert!(leader.is_err());
+        assert_eq!(follower, Err(TestError::Abandoned));
+        assert!(is_empty(&flight));
+    }
+
+    #[rocket::async_test]
+    async fn slow_leader_times_out() {
+        let flight = SingleFlight::<u32, TestError>::default();
+        let (tx, rx) = oneshot::channel();
+
+        let (leader, follower) = join!(
+            flight.run("key", TIMEOUT, async {
+                rx.await.unwrap();
+                Ok(1)
+            }),
+            async {
+                let result = flight
+                    .run("key", Duration::from_millis(50), async { Ok(2) })
+                    .await;
+                tx.send(()).unwrap();
+                result
+            },
+        );
+
+        assert_eq!(leader, Ok(1));
+        assert_eq!(follower, Err(TestError::Timeout));
+        assert!(is_empty(&flight));
+    }
+}
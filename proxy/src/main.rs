@@ -1,52 +1,107 @@
 #![allow(clippy::option_if_let_else)]
 
+mod cache;
+mod catalog;
+mod config;
+mod error;
+mod ffmpeg;
+mod flight;
+mod metrics;
+mod platform;
+mod pool;
+mod refresh;
 mod route;
+mod stream;
 
 #[macro_use]
 extern crate rocket;
 
-use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+use std::{
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use common::youtube_dl::get_youtube_dl_path;
-use regex::Regex;
-use rocket::tokio::sync::RwLock;
+use lru::LruCache;
+use rocket::{
+    fairing::AdHoc,
+    serde::{Deserialize, Serialize},
+    tokio::sync::{Mutex, Semaphore},
+};
 #[cfg(feature = "database")]
 use rocket_db_pools::{
     sqlx::{self},
     Database,
 };
+use which::which;
+
+use crate::{
+    cache::CacheBackend,
+    config::Config,
+    error::ProxyError,
+    flight::SingleFlight,
+    metrics::Metrics,
+    pool::ExtractionPool,
+    route::prelude::*,
+};
 
-use crate::route::prelude::*;
-
-const EXPIRE_REGEX: &str = r"exp(?:ir(?:es?|ation))?=(\d+)";
-const YOUTUBE_REGEX: &str = r"(?x)^/
-    (?:https?://)?
-    (?:www\.)?
-    (?:
-        youtube\.com/watch\?v=|
-        youtube\.com/shorts/|
-        youtu\.be/
-    )?
-    ([0-9A-Za-z_-]{11})
-    (?:.+)?
-$";
+const ALIAS_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+const INVIDIOUS_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(1_000).unwrap();
+const METADATA_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+const PLAYLIST_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(1_000).unwrap();
+const SEARCH_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
 
 #[cfg(feature = "database")]
 #[derive(Database)]
 #[database("VRC_YT")]
 struct VRChatYouTube(sqlx::MySqlPool);
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
 struct CachedVideo {
-    exp: SystemTime,
-    url: String,
+    exp:         SystemTime,
+    url:         String,
+    #[serde(default)]
+    hits:        u64,
+    /// Separate DASH audio stream muxed with `url`
+    #[serde(default)]
+    audio_url:   Option<String>,
+    /// The requested codec wasn't available, so the video has to be transcoded
+    #[serde(default)]
+    transcode:   bool,
+    /// HLS manifest of a live stream, always redirected to because players fetch its segments directly
+    #[serde(default)]
+    live:        bool,
+    /// Thumbnail shown while audio plays in video players
+    #[serde(default)]
+    thumbnail:   Option<String>,
+    /// Reason the video can't be played, see [`common::youtube_dl::YoutubeError::unavailable_reason`]
+    #[serde(default)]
+    unavailable: Option<String>,
 }
 
 struct RocketState {
-    cache: RwLock<HashMap<String, Option<CachedVideo>>>,
-    expire_regex: Regex,
+    /// Cache keys of links to other sites, which are only known once yt-dlp has extracted them
+    aliases: Mutex<LruCache<String, String>>,
+    cache: Arc<dyn CacheBackend>,
+    config: Config,
+    extractions: ExtractionPool,
+    ffmpeg_path: Option<PathBuf>,
+    flights: SingleFlight<CachedVideo, ProxyError>,
+    http: reqwest::Client,
+    invidious: Mutex<LruCache<String, CachedInvidiousVideo>>,
+    invidious_flights: SingleFlight<InvidiousVideo, ProxyError>,
+    invidious_search_flights: SingleFlight<Vec<InvidiousSearchResult>, ProxyError>,
+    invidious_searches: Mutex<LruCache<String, CachedInvidiousSearch>>,
+    metadata: Mutex<LruCache<String, CachedMetadata>>,
+    metrics: Metrics,
+    muxes: Arc<Semaphore>,
+    playlists: Mutex<LruCache<String, CachedPlaylist>>,
+    searches: Mutex<LruCache<String, CachedSearch>>,
+    transcodes: Arc<Semaphore>,
     youtube_dl_path: PathBuf,
-    youtube_regex: Regex,
 }
 
 #[launch]
@@ -54,22 +109,75 @@ async fn rocket() -> _ {
     #[cfg(debug_assertions)]
     dotenvy::dotenv().expect(".env file not found");
 
+    let rocket = rocket::build();
+    let config = rocket.figment().extract::<Config>().unwrap();
+    let cache = cache::from_config(&config).await;
+    let youtube_dl_path = get_youtube_dl_path().await.unwrap();
     let state = RocketState {
-        cache: RwLock::default(),
-        expire_regex: Regex::new(EXPIRE_REGEX).unwrap(),
-        youtube_dl_path: get_youtube_dl_path().await.unwrap(),
-        youtube_regex: Regex::new(YOUTUBE_REGEX).unwrap(),
+        aliases: Mutex::new(LruCache::new(ALIAS_CACHE_CAPACITY)),
+        cache,
+        extractions: ExtractionPool::new(config.max_extractions, config.extraction_queue),
+        ffmpeg_path: which("ffmpeg").ok(),
+        flights: SingleFlight::default(),
+        http: reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(15))
+            .build()
+            .unwrap(),
+        invidious: Mutex::new(LruCache::new(INVIDIOUS_CACHE_CAPACITY)),
+        invidious_flights: SingleFlight::default(),
+        invidious_search_flights: SingleFlight::default(),
+        invidious_searches: Mutex::new(LruCache::new(INVIDIOUS_CACHE_CAPACITY)),
+        metadata: Mutex::new(LruCache::new(METADATA_CACHE_CAPACITY)),
+        metrics: Metrics::new(&youtube_dl_path).await,
+        muxes: Arc::new(Semaphore::new(config.max_muxes)),
+        playlists: Mutex::new(LruCache::new(PLAYLIST_CACHE_CAPACITY)),
+        searches: Mutex::new(LruCache::new(SEARCH_CACHE_CAPACITY)),
+        transcodes: Arc::new(Semaphore::new(config.max_transcodes)),
+        youtube_dl_path,
+        config,
     };
 
+    if state.config.mux && state.ffmpeg_path.is_none() {
+        warn!("ffmpeg wasn't found, resolutions above 720p will be limited to 720p");
+    }
+
+    let state = Arc::new(state);
+    if state.config.refresh_interval > 0 {
+        rocket::tokio::spawn(refresh::refresh(state.clone()));
+    }
+
     #[allow(unused_mut)]
-    let mut rocket = rocket::build()
+    let mut rocket = rocket
         .manage(state)
-        .mount("/", routes![root])
-        .register("/", catchers![proxy]);
+        .mount(
+            "/",
+            routes![
+                api_video,
+                invidious_search,
+                invidious_video,
+                playlist,
+                root,
+                scrape,
+                search
+            ],
+        )
+        .register("/", catchers![proxy])
+        .attach(AdHoc::on_shutdown("Save Cache", |rocket| {
+            Box::pin(async move {
+                let state = rocket.state::<Arc<RocketState>>().unwrap();
+                if let Some(path) = &state.config.cache_path {
+                    if let Err(error) = state.cache.save(path).await {
+                        error!("Unable to save cache to {}: {error}", path.display());
+                    }
+                }
+            })
+        }));
 
     #[cfg(feature = "database")]
     {
-        rocket = rocket.attach(VRChatYouTube::init());
+        rocket = rocket
+            .attach(VRChatYouTube::init())
+            .mount("/", routes![api_search]);
     }
 
     rocket
@@ -0,0 +1,207 @@
+use std::{path::Path, time::Duration};
+
+use common::youtube_dl::YoutubeError;
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use rocket::tokio::process::Command;
+
+use crate::{
+    cache::CacheBackend,
+    error::{youtube_class, ProxyError},
+    pool::ExtractionPool,
+};
+
+/// Buckets in seconds, from cache hits to yt-dlp extractions timing out
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Prometheus metrics of the proxy, served at `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    /// Requests to the proxy catcher by result, `ok` or the class of their error
+    requests: IntCounterVec,
+    /// Seconds spent in each stage of the proxy catcher
+    stages: HistogramVec,
+    /// Cache lookups by result, `hit`, `miss`, `expired`, or `unavailable`
+    cache_lookups: IntCounterVec,
+    /// yt-dlp runs by result, `ok` or the class of their error
+    extractions: IntCounterVec,
+    /// Seconds yt-dlp took to extract a video
+    extraction_time: HistogramVec,
+    /// Failed database queries by query, which are otherwise only printed
+    database_errors: IntCounterVec,
+    /// Entries the cache evicted and swept, counted by the cache itself
+    cache_evictions: IntCounter,
+    cache_expirations: IntCounter,
+    cache_size: IntGauge,
+    extractions_running: IntGauge,
+    extractions_queued: IntGauge,
+}
+
+impl Metrics {
+    /// Register every metric, labeled with the version of the yt-dlp binary at `youtube_dl_path`
+    pub async fn new(youtube_dl_path: &Path) -> Self {
+        let registry = Registry::new_custom(Some("vrc_yt".to_string()), None).unwrap();
+        let counter = |name: &str, help: &str, label: &str| {
+            let counter = IntCounterVec::new(Opts::new(name, help), &[label]).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let histogram = |name: &str, help: &str, label: &str| {
+            let opts = HistogramOpts::new(name, help).buckets(BUCKETS.to_vec());
+            let histogram = HistogramVec::new(opts, &[label]).unwrap();
+            registry.register(Box::new(histogram.clone())).unwrap();
+            histogram
+        };
+        let total = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+
+        let requests = counter(
+            "requests_total",
+            "Requests to the proxy by result",
+            "result",
+        );
+        let stages = histogram(
+            "stage_duration_seconds",
+            "Seconds spent per stage of a request",
+            "stage",
+        );
+        let cache_lookups = counter("cache_lookups_total", "Cache lookups by result", "result");
+        let extractions = counter(
+            "extractions_total",
+            "yt-dlp extractions by result",
+            "result",
+        );
+        let extraction_time = histogram(
+            "extraction_duration_seconds",
+            "Seconds yt-dlp took to extract a video",
+            "result",
+        );
+        let database_errors = counter("database_errors_total", "Failed database queries", "query");
+        let cache_evictions = total(
+            "cache_evictions_total",
+            "Videos evicted from the cache to stay within its capacity",
+        );
+        let cache_expirations = total(
+            "cache_expirations_total",
+            "Expired videos swept from the cache",
+        );
+        let cache_size = gauge("cache_entries", "Unexpired videos in the cache");
+        let extractions_running = gauge("extractions_running", "yt-dlp processes running");
+        let extractions_queued = gauge(
+            "extractions_queued",
+            "Requests waiting for a yt-dlp process",
+        );
+
+        let youtube_dl_version =
+            IntGaugeVec::new(Opts::new("yt_dlp_info", "Version of yt-dlp"), &["version"]).unwrap();
+        registry
+            .register(Box::new(youtube_dl_version.clone()))
+            .unwrap();
+        youtube_dl_version
+            .with_label_values(&[&get_youtube_dl_version(youtube_dl_path).await])
+            .set(1);
+
+        Self {
+            registry,
+            requests,
+            stages,
+            cache_lookups,
+            extractions,
+            extraction_time,
+            database_errors,
+            cache_evictions,
+            cache_expirations,
+            cache_size,
+            extractions_running,
+            extractions_queued,
+        }
+    }
+
+    /// Count a finished request by its result
+    pub fn request<T>(&self, result: &Result<T, ProxyError>) {
+        let label = result.as_ref().map_or_else(ProxyError::class, |_| "ok");
+        self.requests.with_label_values(&[label]).inc();
+    }
+
+    pub fn stage(&self, stage: &str, elapsed: Duration) {
+        self.stages
+            .with_label_values(&[stage])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn cache_lookup(&self, result: &str) {
+        self.cache_lookups.with_label_values(&[result]).inc();
+    }
+
+    /// Count a yt-dlp run and how long it took by its result
+    pub fn extraction<T>(&self, result: &Result<T, YoutubeError>, elapsed: Duration) {
+        let label = result.as_ref().map_or_else(youtube_class, |_| "ok");
+        self.extractions.with_label_values(&[label]).inc();
+        self.extraction_time
+            .with_label_values(&[label])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn database_error(&self, query: &str) {
+        self.database_errors.with_label_values(&[query]).inc();
+    }
+
+    /// Every metric in the Prometheus text format, with the gauges measured at the time of the scrape
+    pub async fn encode(&self, cache: &dyn CacheBackend, extractions: &ExtractionPool) -> String {
+        let gauge = |value: usize| i64::try_from(value).unwrap_or(i64::MAX);
+        self.cache_size.set(gauge(cache.size().await));
+        self.extractions_running.set(gauge(extractions.running()));
+        self.extractions_queued.set(gauge(extractions.queued()));
+
+        // The cache keeps its own totals, the counters catch up to them
+        for (counter, total) in [
+            (&self.cache_evictions, cache.evictions()),
+            (&self.cache_expirations, cache.expirations()),
+        ] {
+            counter.inc_by(total.saturating_sub(counter.get()));
+        }
+
+        let mut buffer = Vec::new();
+        if let Err(error) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!("Unable to encode metrics: {error}");
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// First line of `yt-dlp --version`, `unknown` if it can't be run
+async fn get_youtube_dl_version(youtube_dl_path: &Path) -> String {
+    let output = Command::new(youtube_dl_path)
+        .arg("--version")
+        .output()
+        .await;
+    let version = output.ok().and_then(|output| {
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        stdout.lines().next().map(|line| line.trim().to_string())
+    });
+
+    version
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
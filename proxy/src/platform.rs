@@ -0,0 +1,47 @@
+use std::convert::Infallible;
+
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+/// Video player requesting a video, detected from its User-Agent
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Platform {
+    /// `AVPro` on Android, backed by `ExoPlayer`
+    Quest,
+
+    /// Unity video player or `AVPro` on Windows, backed by Media Foundation
+    Pc,
+
+    /// Browsers and anything else
+    Other,
+}
+
+impl Platform {
+    #[must_use]
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        let user_agent = user_agent.to_lowercase();
+        let contains =
+            |patterns: &[&str]| patterns.iter().any(|pattern| user_agent.contains(pattern));
+
+        if contains(&["android", "exoplayer", "stagefright"]) {
+            Self::Quest
+        } else if contains(&["unityplayer", "nsplayer", "windows-media-player", "wmfsdk"]) {
+            Self::Pc
+        } else {
+            Self::Other
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Platform {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user_agent = req.headers().get_one("User-Agent").unwrap_or_default();
+
+        Outcome::Success(Self::from_user_agent(user_agent))
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rocket::tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Limits how many yt-dlp processes run at once and how many requests may queue for one
+pub struct ExtractionPool {
+    permits: Semaphore,
+    queued:  AtomicUsize,
+    depth:   usize,
+    max:     usize,
+}
+
+impl ExtractionPool {
+    #[must_use]
+    pub fn new(max_extractions: usize, queue_depth: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_extractions),
+            queued:  AtomicUsize::new(0),
+            depth:   queue_depth,
+            max:     max_extractions,
+        }
+    }
+
+    /// Number of yt-dlp processes running
+    pub fn running(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+
+    /// Number of requests waiting for a yt-dlp process
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Wait for a free extraction slot, returns `None` if the queue is already full
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        if let Ok(permit) = self.permits.try_acquire() {
+            return Some(permit);
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.depth {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let _guard = QueueGuard(&self.queued);
+        self.permits.acquire().await.ok()
+    }
+}
+
+/// Leaves the queue when the waiting request is served or dropped
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::tokio::{pin, time};
+
+    use super::*;
+
+    #[rocket::async_test]
+    async fn rejects_when_queue_is_full() {
+        let pool = ExtractionPool::new(1, 1);
+        let permit = pool.acquire().await.unwrap();
+
+        let queued = pool.acquire();
+        pin!(queued);
+        assert!(time::timeout(Duration::from_millis(50), &mut queued)
+            .await
+            .is_err());
+        assert_eq!((pool.running(), pool.queued()), (1, 1));
+
+        assert!(pool.acquire().await.is_none());
+        assert_eq!(pool.queued(), 1);
+
+        drop(permit);
+        assert!(queued.await.is_some());
+        assert_eq!(pool.queued(), 0);
+    }
+}
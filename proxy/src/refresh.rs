@@ -0,0 +1,69 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rocket::tokio::time;
+
+use crate::{
+    route::prelude::{parse_cache_key, resolve},
+    RocketState,
+};
+
+/// Periodically re-resolve popular videos shortly before they expire, so viewers never wait on yt-dlp
+pub async fn refresh(state: Arc<RocketState>) {
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    let window = Duration::from_secs(state.config.refresh_window);
+    let mut interval = time::interval(Duration::from_secs(state.config.refresh_interval));
+
+    loop {
+        interval.tick().await;
+
+        let before = SystemTime::now() + window;
+        let keys = state
+            .cache
+            .expiring(before, state.config.refresh_min_hits)
+            .await;
+
+        for key in keys {
+            let Ok((video_id, format)) = parse_cache_key(&key) else {
+                warn!("Unable to parse cache key {key}");
+                continue;
+            };
+
+            // Other sites are keyed by extractor and id, which can't be turned back into a link
+            if video_id.contains(':') {
+                continue;
+            }
+
+            let video_url = format!("https://youtu.be/{video_id}");
+            if !state.cache.lock(&key, flight_timeout).await {
+                debug!("{key} is being refreshed by another instance");
+                continue;
+            }
+
+            // Another instance may have refreshed it since the expiring videos were listed,
+            // and unavailable videos are left to expire so the next request retries them
+            if let Some(cached_video) = state.cache.get(&key).await {
+                if cached_video.exp > before || cached_video.unavailable.is_some() {
+                    state.cache.unlock(&key).await;
+                    continue;
+                }
+            }
+
+            let result = state
+                .flights
+                .run(&key, flight_timeout, async {
+                    let (cached_video, _) = resolve(&state, &key, &video_url, format).await?;
+                    Ok(cached_video)
+                })
+                .await;
+
+            state.cache.unlock(&key).await;
+            match result {
+                Ok(_) => info!("Refreshed {video_url} before it expired"),
+                Err(error) => warn!("Unable to refresh {video_url}: {error}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use common::sqlx::{search_videos, SearchResult};
+use rocket::{
+    http::{uri::Host, ContentType},
+    serde::{json::Json, Serialize},
+    Either,
+    State,
+};
+use rocket_db_pools::Connection;
+
+use super::playlist::{base_url, protv, PlaylistEntry};
+use crate::{error::ProxyError, RocketState, VRChatYouTube};
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SearchPage {
+    query:     String,
+    page:      u32,
+    /// `None` on the last page
+    next_page: Option<u32>,
+    results:   Vec<SearchHit>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SearchHit {
+    id:         String,
+    title:      String,
+    channel_id: String,
+    channel:    Option<String>,
+    tags:       Vec<String>,
+    /// Proxy link that plays the video
+    url:        String,
+}
+
+/// Search videos played through the proxy by title, tags, or channel, as JSON or a `ProTV` playlist
+#[get("/api/search?<q>&<page>&<format>")]
+pub async fn api_search(
+    q: &str,
+    page: Option<u32>,
+    format: Option<&str>,
+    host: Option<&Host<'_>>,
+    mut conn: Connection<VRChatYouTube>,
+    state: &State<Arc<RocketState>>,
+) -> Result<Either<Json<SearchPage>, (ContentType, String)>, ProxyError> {
+    let query = q.trim();
+    let max_length = state.config.search_max_length;
+    if query.is_empty() || query.chars().count() > max_length {
+        return Err(ProxyError::SearchQuery(max_length));
+    }
+
+    let page = page.unwrap_or(1).max(1);
+    let page_size = state.config.catalog_page_size;
+    let offset = (page - 1).saturating_mul(page_size);
+
+    debug!("Attempting to search the catalog for {query}");
+    // One extra result tells whether there's another page
+    let mut results = search_videos(&mut conn, query, page_size + 1, offset)
+        .await
+        .map_err(|error| {
+            error!("Error searching videos: {error}");
+            state.metrics.database_error("search_videos");
+            ProxyError::Catalog
+        })?;
+    let next_page = (results.len() > page_size as usize).then_some(page + 1);
+    results.truncate(page_size as usize);
+
+    let base_url = base_url(state, host);
+    match format.unwrap_or("json") {
+        "json" => Ok(Either::Left(Json(SearchPage {
+            query: query.to_string(),
+            page,
+            next_page,
+            results: results
+                .into_iter()
+                .map(|result| SearchHit {
+                    url:        format!("{base_url}/{}", result.id),
+                    id:         result.id,
+                    title:      result.title,
+                    channel_id: result.channel_id,
+                    channel:    result.channel_name,
+                    tags:       result.tags.0.unwrap_or_default(),
+                })
+                .collect(),
+        }))),
+        "protv" => {
+            let entries = results.into_iter().map(into_entry).collect::<Vec<_>>();
+            Ok(Either::Right((
+                ContentType::Plain,
+                protv(&entries, &base_url),
+            )))
+        }
+        format => Err(ProxyError::SearchFormat(format.to_string())),
+    }
+}
+
+fn into_entry(result: SearchResult) -> PlaylistEntry {
+    PlaylistEntry {
+        id:       result.id,
+        title:    result.title,
+        channel:  result.channel_name,
+        duration: None,
+        tags:     result.tags.0.unwrap_or_default(),
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+#[cfg(feature = "database")]
+use common::sqlx::{search_videos, SearchResult};
+use common::{
+    url::{expiry, is_video_id},
+    youtube_dl::{
+        get_live_url,
+        get_single_video_async,
+        search_async,
+        Format,
+        Protocol,
+        SingleVideo,
+        VideoFormat,
+    },
+};
+use rocket::{
+    response::{self, Responder},
+    serde::{
+        json::{json, Json, Value},
+        Serialize,
+    },
+    Request,
+    State,
+};
+
+use super::video::remember_metadata;
+use crate::{catalog::Catalog, error::ProxyError, RocketState};
+
+/// Results per page of `/api/v1/search`, the same as Invidious
+const SEARCH_PAGE_SIZE: u32 = 20;
+
+/// yt-dlp searches from the first result, so later pages get slower
+const SEARCH_MAX_PAGE: u32 = 5;
+
+/// Errors in the `{"error": "..."}` shape Invidious clients expect
+pub struct InvidiousError(ProxyError);
+
+impl From<ProxyError> for InvidiousError {
+    fn from(error: ProxyError) -> Self {
+        Self(error)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for InvidiousError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.0.status();
+        let mut response =
+            (status, Json(json!({ "error": self.0.to_string() }))).respond_to(req)?;
+        if let ProxyError::Busy(retry_after) = self.0 {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct InvidiousVideo {
+    r#type: &'static str,
+    title: String,
+    video_id: String,
+    video_thumbnails: Vec<Thumbnail>,
+    description: String,
+    published: i64,
+    keywords: Vec<String>,
+    view_count: i64,
+    like_count: i64,
+    author: String,
+    author_id: String,
+    author_url: String,
+    length_seconds: i64,
+    live_now: bool,
+    is_upcoming: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hls_url: Option<String>,
+    adaptive_formats: Vec<AdaptiveFormat>,
+    format_streams: Vec<FormatStream>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct InvidiousSearchResult {
+    r#type: &'static str,
+    title: String,
+    video_id: String,
+    author: String,
+    author_id: String,
+    author_url: String,
+    video_thumbnails: Vec<Thumbnail>,
+    description: String,
+    view_count: i64,
+    published: i64,
+    length_seconds: i64,
+    live_now: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Thumbnail {
+    quality: String,
+    url:     String,
+    width:   i64,
+    height:  i64,
+}
+
+/// Format with both video and audio
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct FormatStream {
+    url:           String,
+    itag:          String,
+    r#type:        String,
+    quality:       String,
+    container:     String,
+    encoding:      String,
+    quality_label: String,
+    resolution:    String,
+    size:          String,
+}
+
+/// Format with only video or only audio
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+struct AdaptiveFormat {
+    url:        String,
+    itag:       String,
+    r#type:     String,
+    bitrate:    String,
+    clen:       String,
+    container:  String,
+    encoding:   String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size:       Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fps:        Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct CachedInvidiousVideo {
+    exp:   SystemTime,
+    video: InvidiousVideo,
+}
+
+#[derive(Clone)]
+pub struct CachedInvidiousSearch {
+    exp:     SystemTime,
+    results: Vec<InvidiousSearchResult>,
+}
+
+/// Invidious `/api/v1/videos/<id>`, for tools that already speak its API
+#[get("/api/v1/videos/<video_id>")]
+pub async fn invidious_video(
+    video_id: &str,
+    state: &State<Arc<RocketState>>,
+) -> Result<Json<InvidiousVideo>, InvidiousError> {
+    if !is_video_id(video_id) {
+        return Err(ProxyError::VideoId.into());
+    }
+
+    debug!("Checking if Invidious video {video_id} is in the cache");
+    if let Some(cached_video) = state.invidious.lock().await.get(video_id) {
+        if cached_video.exp > SystemTime::now() {
+            return Ok(Json(cached_video.video.clone()));
+        }
+    }
+
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    let video = state
+        .invidious_flights
+        .run(video_id, flight_timeout, get_video(state, video_id))
+        .await?;
+
+    Ok(Json(video))
+}
+
+/// Invidious `/api/v1/search`, only videos are searched for, in the catalog if it has any
+#[cfg_attr(not(feature = "database"), allow(unused_mut, unused_variables))]
+#[get("/api/v1/search?<q>&<page>&<type>")]
+pub async fn invidious_search(
+    q: &str,
+    page: Option<u32>,
+    r#type: Option<&str>,
+    mut catalog: Catalog,
+    state: &State<Arc<RocketState>>,
+) -> Result<Json<Vec<InvidiousSearchResult>>, InvidiousError> {
+    let query = q.trim();
+    let max_length = state.config.search_max_length;
+    if query.is_empty() || query.chars().count() > max_length {
+        return Err(ProxyError::SearchQuery(max_length).into());
+    }
+
+    let page = page.unwrap_or(1).max(1);
+    if page > SEARCH_MAX_PAGE || !matches!(r#type, None | Some("video" | "all")) {
+        return Ok(Json(Vec::new()));
+    }
+
+    #[cfg(feature = "database")]
+    if let Some(results) = search_catalog(&mut catalog, state, query, page).await {
+        return Ok(Json(results));
+    }
+
+    let key = format!("{page}:{query}");
+    debug!("Checking if Invidious search {key} is in the cache");
+    if let Some(cached_search) = state.invidious_searches.lock().await.get(&key) {
+        if cached_search.exp > SystemTime::now() {
+            return Ok(Json(cached_search.results.clone()));
+        }
+    }
+
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    let results = state
+        .invidious_search_flights
+        .run(&key, flight_timeout, async {
+            let results = search_youtube(state, query, page).await?;
+            let cached_search = CachedInvidiousSearch {
+                exp:     SystemTime::now() + Duration::from_secs(state.config.search_ttl),
+                results: results.clone(),
+            };
+            state
+                .invidious_searches
+                .lock()
+                .await
+                .put(key.clone(), cached_search);
+
+            Ok(results)
+        })
+        .await?;
+
+    Ok(Json(results))
+}
+
+/// Get a video with yt-dlp and cache it until its format urls expire
+async fn get_video(state: &RocketState, video_id: &str) -> Result<InvidiousVideo, ProxyError> {
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    let video_url = format!("https://youtu.be/{video_id}");
+    debug!("Attempting to get single video with yt-dlp");
+    let start = Instant::now();
+    let result =
+        get_single_video_async(&state.youtube_dl_path, &video_url, true, Format::default()).await;
+    drop(permit);
+
+    state.metrics.extraction(&result, start.elapsed());
+    let single_video =
+        result.inspect_err(|error| warn!("Unable to get {video_url} with yt-dlp: {error}"))?;
+
+    remember_metadata(state, &single_video).await;
+    let video = into_video(&single_video);
+
+    // Format urls stop working when they expire, so the response can't be cached past that
+    let ttl = state.config.metadata_ttl;
+    let exp = single_video
+        .formats
+        .iter()
+        .flatten()
+        .filter_map(|format| expiry(format.url.as_deref()?))
+        .min()
+        .map(|secs| secs.saturating_sub(state.config.expiry_margin))
+        .map_or_else(
+            || SystemTime::now() + Duration::from_secs(ttl),
+            |secs| {
+                (SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                    .min(SystemTime::now() + Duration::from_secs(ttl))
+            },
+        );
+    state.invidious.lock().await.put(
+        video_id.to_string(),
+        CachedInvidiousVideo {
+            exp,
+            video: video.clone(),
+        },
+    );
+
+    Ok(video)
+}
+
+/// A page of catalog search results, `None` if the catalog is unreachable or can't fill the page
+#[cfg(feature = "database")]
+async fn search_catalog(
+    catalog: &mut Catalog,
+    state: &RocketState,
+    query: &str,
+    page: u32,
+) -> Option<Vec<InvidiousSearchResult>> {
+    let conn = catalog.conn.as_mut()?;
+    let offset = (page - 1) * SEARCH_PAGE_SIZE;
+
+    debug!("Attempting to search the catalog for {query}");
+    let results = match search_videos(conn, query, SEARCH_PAGE_SIZE, offset).await {
+        Ok(results) => results,
+        Err(error) => {
+            error!("Error searching videos: {error}");
+            state.metrics.database_error("search_videos");
+            return None;
+        }
+    };
+
+    // Catalog results lack length, views, and upload date, so a page it can't fill is searched on YouTube
+    if results.len() < SEARCH_PAGE_SIZE as usize {
+        return None;
+    }
+
+    Some(results.into_iter().map(into_catalog_result).collect())
+}
+
+/// A page of `YouTube` search results, yt-dlp searches from the first result so every earlier page is fetched too
+async fn search_youtube(
+    state: &RocketState,
+    query: &str,
+    page: u32,
+) -> Result<Vec<InvidiousSearchResult>, ProxyError> {
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    info!("Searching for {query}...");
+    let results = search_async(
+        &state.youtube_dl_path,
+        query,
+        (page * SEARCH_PAGE_SIZE) as usize,
+    )
+    .await
+    .inspect_err(|error| warn!("Unable to search for {query} with yt-dlp: {error}"))?;
+    drop(permit);
+
+    Ok(results
+        .iter()
+        .skip(((page - 1) * SEARCH_PAGE_SIZE) as usize)
+        .map(into_search_result)
+        .collect())
+}
+
+fn into_video(single_video: &SingleVideo) -> InvidiousVideo {
+    let formats = single_video.formats.as_deref().unwrap_or_default();
+    let live_now = single_video.is_live.unwrap_or_default();
+
+    InvidiousVideo {
+        r#type: "video",
+        title: single_video.title.clone().unwrap_or_default(),
+        video_id: single_video.id.clone(),
+        video_thumbnails: thumbnails(single_video),
+        description: single_video.description.clone().unwrap_or_default(),
+        published: int(single_video.timestamp),
+        keywords: single_video
+            .tags
+            .iter()
+            .flatten()
+            .flatten()
+            .cloned()
+            .collect(),
+        view_count: single_video.view_count.unwrap_or_default(),
+        like_count: single_video.like_count.unwrap_or_default(),
+        author: author(single_video),
+        author_id: single_video.channel_id.clone().unwrap_or_default(),
+        author_url: author_url(single_video),
+        length_seconds: length_seconds(single_video),
+        live_now,
+        is_upcoming: false,
+        hls_url: live_now
+            .then(|| get_live_url(single_video, Format::default()).ok())
+            .flatten(),
+        adaptive_formats: formats.iter().filter_map(into_adaptive_format).collect(),
+        format_streams: formats.iter().filter_map(into_format_stream).collect(),
+    }
+}
+
+fn into_search_result(single_video: &SingleVideo) -> InvidiousSearchResult {
+    InvidiousSearchResult {
+        r#type: "video",
+        title: single_video.title.clone().unwrap_or_default(),
+        video_id: single_video.id.clone(),
+        author: author(single_video),
+        author_id: single_video.channel_id.clone().unwrap_or_default(),
+        author_url: author_url(single_video),
+        video_thumbnails: thumbnails(single_video),
+        description: single_video.description.clone().unwrap_or_default(),
+        view_count: single_video.view_count.unwrap_or_default(),
+        published: int(single_video.timestamp),
+        length_seconds: length_seconds(single_video),
+        live_now: single_video.is_live.unwrap_or_default(),
+    }
+}
+
+/// Catalog rows only have the title, channel, and tags, the rest is left empty like Invidious does for unknowns
+#[cfg(feature = "database")]
+fn into_catalog_result(result: SearchResult) -> InvidiousSearchResult {
+    InvidiousSearchResult {
+        r#type: "video",
+        title: result.title,
+        video_thumbnails: catalog_thumbnails(&result.id),
+        video_id: result.id,
+        author: result.channel_name.unwrap_or_default(),
+        author_url: format!("/channel/{}", result.channel_id),
+        author_id: result.channel_id,
+        description: String::new(),
+        view_count: 0,
+        published: 0,
+        length_seconds: 0,
+        live_now: false,
+    }
+}
+
+/// Progressive http formats with video and audio, what Invidious calls format streams
+fn into_format_stream(format: &VideoFormat) -> Option<FormatStream> {
+    let (vcodec, acodec) = codecs(format)?;
+    let (Some(vcodec), Some(acodec)) = (vcodec, acodec) else {
+        return None;
+    };
+
+    let container = format.ext.clone().unwrap_or_default();
+    let quality_label = format!("{}p", int(format.height));
+    Some(FormatStream {
+        url: format.url.clone()?,
+        itag: format.format_id.clone()?,
+        r#type: format!("video/{container}; codecs=\"{vcodec}, {acodec}\""),
+        quality: format
+            .format_note
+            .clone()
+            .unwrap_or_else(|| quality_label.clone()),
+        container,
+        encoding: vcodec.to_string(),
+        resolution: quality_label.clone(),
+        quality_label,
+        size: format!("{}x{}", int(format.width), int(format.height)),
+    })
+}
+
+/// Http formats with only video or only audio, what Invidious calls adaptive formats
+fn into_adaptive_format(format: &VideoFormat) -> Option<AdaptiveFormat> {
+    let (vcodec, acodec) = codecs(format)?;
+    let (kind, codec) = match (vcodec, acodec) {
+        (Some(vcodec), None) => ("video", vcodec),
+        (None, Some(acodec)) => ("audio", acodec),
+        _ => return None,
+    };
+
+    let container = format.ext.clone().unwrap_or_default();
+    let is_video = kind == "video";
+    Some(AdaptiveFormat {
+        url: format.url.clone()?,
+        itag: format.format_id.clone()?,
+        r#type: format!("{kind}/{container}; codecs=\"{codec}\""),
+        bitrate: int(format.tbr.map(|tbr| tbr * 1000.0)).to_string(),
+        clen: int(format.filesize.or(format.filesize_approx)).to_string(),
+        container,
+        encoding: codec.to_string(),
+        resolution: is_video.then(|| format!("{}p", int(format.height))),
+        size: is_video.then(|| format!("{}x{}", int(format.width), int(format.height))),
+        fps: is_video.then(|| int(format.fps)),
+    })
+}
+
+/// Video and audio codecs of an http format, `None` for manifests and formats without either
+fn codecs(format: &VideoFormat) -> Option<(Option<&str>, Option<&str>)> {
+    let is_http = matches!(
+        format.protocol,
+        None | Some(Protocol::Http | Protocol::Https)
+    ) && format
+        .url
+        .as_deref()
+        .is_some_and(|url| url.starts_with("https://"));
+    if !is_http {
+        return None;
+    }
+
+    let vcodec = format.vcodec.as_deref().filter(|vcodec| *vcodec != "none");
+    let acodec = format.acodec.as_deref().filter(|acodec| *acodec != "none");
+    Some((vcodec, acodec))
+}
+
+fn thumbnails(single_video: &SingleVideo) -> Vec<Thumbnail> {
+    let thumbnails = single_video
+        .thumbnails
+        .iter()
+        .flatten()
+        .filter_map(|thumbnail| {
+            Some(Thumbnail {
+                quality: thumbnail
+                    .id
+                    .clone()
+                    .unwrap_or_else(|| "default".to_string()),
+                url:     thumbnail.url.clone()?,
+                width:   int(thumbnail.width),
+                height:  int(thumbnail.height),
+            })
+        });
+
+    let thumbnail = single_video.thumbnail.clone().map(|url| Thumbnail {
+        quality: "maxresdefault".to_string(),
+        url,
+        width: 0,
+        height: 0,
+    });
+
+    thumbnails.chain(thumbnail).collect()
+}
+
+/// Thumbnails `YouTube` has for every video, in the qualities Invidious lists
+#[cfg(feature = "database")]
+fn catalog_thumbnails(video_id: &str) -> Vec<Thumbnail> {
+    [
+        ("maxres", "maxresdefault", 1280, 720),
+        ("sddefault", "sddefault", 640, 480),
+        ("high", "hqdefault", 480, 360),
+        ("medium", "mqdefault", 320, 180),
+        ("default", "default", 120, 90),
+    ]
+    .into_iter()
+    .map(|(quality, file, width, height)| Thumbnail {
+        quality: quality.to_string(),
+        url: format!("https://i.ytimg.com/vi/{video_id}/{file}.jpg"),
+        width,
+        height,
+    })
+    .collect()
+}
+
+fn author(single_video: &SingleVideo) -> String {
+    single_video
+        .channel
+        .clone()
+        .or_else(|| single_video.uploader.clone())
+        .unwrap_or_default()
+}
+
+fn author_url(single_video: &SingleVideo) -> String {
+    single_video
+        .channel_id
+        .as_ref()
+        .map(|channel_id| format!("/channel/{channel_id}"))
+        .unwrap_or_default()
+}
+
+fn length_seconds(single_video: &SingleVideo) -> i64 {
+    int(single_video.duration.as_ref().and_then(Value::as_f64))
+}
+
+/// Invidious has integers where yt-dlp has floats, missing values are 0
+#[allow(clippy::cast_possible_truncation)]
+fn int(value: Option<f64>) -> i64 {
+    value.map_or(0, |value| value as i64)
+}
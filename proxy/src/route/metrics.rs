@@ -0,0 +1,17 @@
+use std::sync::Arc;
+
+use rocket::{http::ContentType, State};
+
+use crate::RocketState;
+
+/// Prometheus metrics of this instance
+#[get("/metrics")]
+pub async fn scrape(state: &State<Arc<RocketState>>) -> (ContentType, String) {
+    let metrics = state
+        .metrics
+        .encode(state.cache.as_ref(), &state.extractions)
+        .await;
+
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, metrics)
+}
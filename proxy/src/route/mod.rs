@@ -1,4 +1,11 @@
 pub mod prelude;
 
+#[cfg(feature = "database")]
+mod api;
+mod invidious;
+mod metrics;
+mod playlist;
 mod proxy;
 mod root;
+mod search;
+mod video;
@@ -0,0 +1,154 @@
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use common::{url::is_playlist_id, youtube_dl::get_playlist_async};
+use rocket::{
+    http::{uri::Host, ContentType},
+    State,
+};
+
+use crate::{error::ProxyError, RocketState};
+
+#[derive(Clone)]
+pub struct CachedPlaylist {
+    exp:     SystemTime,
+    entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Clone)]
+pub struct PlaylistEntry {
+    pub id:       String,
+    pub title:    String,
+    pub channel:  Option<String>,
+    pub duration: Option<f64>,
+    /// Written after the id in `ProTV` playlists, which search them
+    pub tags:     Vec<String>,
+}
+
+/// Entries of a playlist as an M3U or `ProTV` playlist pointing back at the proxy
+#[get("/playlist/<list_id>?<format>")]
+pub async fn playlist(
+    list_id: &str,
+    format: Option<&str>,
+    host: Option<&Host<'_>>,
+    state: &State<Arc<RocketState>>,
+) -> Result<(ContentType, String), ProxyError> {
+    debug!("Attempting to validate playlist id");
+    if !is_playlist_id(list_id) {
+        return Err(ProxyError::PlaylistId);
+    }
+
+    let base_url = base_url(state, host);
+    let entries = get_entries(state, list_id).await?;
+    match format.unwrap_or("m3u") {
+        "m3u" => Ok((
+            ContentType::new("audio", "mpegurl"),
+            m3u(&entries, &base_url),
+        )),
+        "protv" => Ok((ContentType::Plain, protv(&entries, &base_url))),
+        format => Err(ProxyError::PlaylistFormat(format.to_string())),
+    }
+}
+
+/// Url of the proxy, `public_url` or `https://` and the `Host` header
+#[must_use]
+pub fn base_url(state: &RocketState, host: Option<&Host<'_>>) -> String {
+    match (&state.config.public_url, host) {
+        (Some(public_url), _) => public_url.trim_end_matches('/').to_string(),
+        (None, Some(host)) => format!("https://{host}"),
+        (None, None) => String::new(),
+    }
+}
+
+/// Entries of a playlist from the cache, or yt-dlp if it's missing or expired
+async fn get_entries(state: &RocketState, list_id: &str) -> Result<Vec<PlaylistEntry>, ProxyError> {
+    debug!("Checking if playlist {list_id} is in the cache");
+    if let Some(cached_playlist) = state.playlists.lock().await.get(list_id) {
+        if cached_playlist.exp > SystemTime::now() {
+            return Ok(cached_playlist.entries.clone());
+        }
+    }
+
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    let playlist_url = format!("https://www.youtube.com/playlist?list={list_id}");
+    info!("Processing {playlist_url}...");
+
+    debug!("Attempting to get playlist with yt-dlp");
+    let playlist = get_playlist_async(&state.youtube_dl_path, &playlist_url, true)
+        .await
+        .inspect_err(|error| warn!("Unable to get {playlist_url} with yt-dlp: {error}"))?;
+    drop(permit);
+
+    let entries = playlist
+        .entries
+        .unwrap_or_default()
+        .into_iter()
+        .map(|single_video| PlaylistEntry {
+            title:    single_video
+                .title
+                .unwrap_or_else(|| single_video.id.clone()),
+            id:       single_video.id,
+            channel:  single_video.channel.or(single_video.uploader),
+            duration: single_video.duration.and_then(|duration| duration.as_f64()),
+            tags:     Vec::new(),
+        })
+        .collect::<Vec<_>>();
+
+    debug!("Updating cache with playlist entries");
+    let cached_playlist = CachedPlaylist {
+        exp:     SystemTime::now() + Duration::from_secs(state.config.playlist_ttl),
+        entries: entries.clone(),
+    };
+    state
+        .playlists
+        .lock()
+        .await
+        .put(list_id.to_string(), cached_playlist);
+
+    info!("Processed {playlist_url}");
+    Ok(entries)
+}
+
+fn display_title(entry: &PlaylistEntry) -> String {
+    match &entry.channel {
+        Some(channel) => format!("{channel} - {}", entry.title),
+        None => entry.title.clone(),
+    }
+}
+
+fn m3u(entries: &[PlaylistEntry], base_url: &str) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    for entry in entries {
+        #[allow(clippy::cast_possible_truncation)]
+        let duration = entry.duration.map_or(-1, |duration| duration as i64);
+        let _ = writeln!(m3u, "#EXTINF:{duration},{}", display_title(entry));
+        let _ = writeln!(m3u, "{base_url}/{}", entry.id);
+    }
+
+    m3u
+}
+
+/// Same layout as the playlists generated by the manager
+#[must_use]
+pub fn protv(entries: &[PlaylistEntry], base_url: &str) -> String {
+    let mut protv = String::new();
+    for entry in entries {
+        let _ = writeln!(protv, "@{base_url}/{}", entry.id);
+        let _ = write!(protv, "#{}", entry.id);
+        for tag in &entry.tags {
+            let _ = write!(protv, " {tag}");
+        }
+        let _ = writeln!(protv);
+        let _ = writeln!(protv, "{}", display_title(entry));
+        let _ = writeln!(protv);
+    }
+
+    protv
+}
@@ -1 +1,3 @@
-pub use super::{proxy::*, root::*};
+#[cfg(feature = "database")]
+pub use super::api::*;
+pub use super::{invidious::*, metrics::*, playlist::*, proxy::*, root::*, search::*, video::*};
@@ -1,112 +1,652 @@
-use std::time::{Duration, SystemTime};
+use std::{
+    iter,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 #[cfg(feature = "database")]
-use common::sqlx::{insert_channel, upsert_video, Channel, Video};
-use common::youtube_dl::{get_format_url, get_single_video};
-use rocket::{response::Redirect, tokio::time, Request, State};
+use common::sqlx::{insert_channel, set_video_unavailable, upsert_video, Channel, Video};
+use common::{
+    url,
+    youtube_dl::{
+        get_format_urls,
+        get_live_url,
+        get_single_video_async,
+        is_codec,
+        Codec,
+        Format,
+        SingleVideo,
+        YoutubeError,
+        PROGRESSIVE_MAX_RES,
+    },
+};
+use rocket::{response::Redirect, Request, State};
 #[cfg(feature = "database")]
 use rocket_db_pools::Connection;
 
+use super::video::remember_metadata;
 #[cfg(feature = "database")]
 use crate::VRChatYouTube;
-use crate::{CachedVideo, RocketState};
+use crate::{
+    cache::{claim, Claim},
+    catalog::Catalog,
+    error::ProxyError,
+    ffmpeg,
+    platform::Platform,
+    stream::{is_ip_pinned, Passthrough, Stream, StreamMode},
+    CachedVideo,
+    RocketState,
+};
 
 #[catch(404)]
-pub async fn proxy(req: &Request<'_>) -> Result<Redirect, &'static str> {
-    #[cfg(feature = "database")]
-    let mut conn = req.guard::<Connection<VRChatYouTube>>().await.unwrap();
-    let state = req.guard::<&'_ State<RocketState>>().await.unwrap();
+pub async fn proxy(req: &Request<'_>) -> Result<Stream, ProxyError> {
+    let state = req.guard::<&'_ State<Arc<RocketState>>>().await.unwrap();
+    let start = Instant::now();
+    let result = handle(req, state).await;
+    state.metrics.stage("total", start.elapsed());
+    state.metrics.request(&result);
+
+    result
+}
+
+async fn handle(req: &Request<'_>, state: &RocketState) -> Result<Stream, ProxyError> {
+    let mut catalog = req.guard::<Catalog>().await.unwrap();
+    let start = Instant::now();
     let request_uri = req.uri().to_string();
 
-    debug!("Attempting to capture video id from request uri with regex");
-    let Some(captures) = state.youtube_regex.captures(&request_uri) else {
-        return Err("Unable to capture video id from request uri with regex");
+    // `/audio/<video>` accepts the same forms as `/<video>`
+    let (request_uri, audio_only) = match request_uri.strip_prefix("/audio/") {
+        Some(request_uri) => (format!("/{request_uri}"), true),
+        None => (request_uri, false),
     };
 
-    debug!("Attempting to get video id from capture");
-    let Some(video_id) = captures.get(1).map(|m| m.as_str()) else {
-        return Err("Unable to get video id from capture");
+    debug!("Attempting to parse link from request uri");
+    let Some(target) = url::parse(&request_uri) else {
+        return generic(req, state, &request_uri, audio_only).await;
     };
 
+    debug!("Attempting to get video id from link");
+    let Some(video_id) = target.video_id.as_deref() else {
+        // Links to a playlist without a video play the whole playlist
+        if let Some(playlist_id) = target.playlist_id {
+            return Ok(Stream::Redirect(Redirect::temporary(format!(
+                "/playlist/{playlist_id}"
+            ))));
+        }
+
+        return Err(ProxyError::VideoId);
+    };
+
+    let offset = target.start;
+    if let Some(offset) = offset {
+        debug!("Link starts {offset} seconds into {video_id}");
+    }
+
+    let format = select_format(req, state, audio_only).await?;
+    let cover = wants_cover(req, format);
     let video_url = format!("https://youtu.be/{video_id}");
+    let key = cache_key(video_id, format);
+    state.metrics.stage("parse", start.elapsed());
     info!("Processing {video_url}...");
 
-    loop {
-        debug!("Checking if {video_id} is in the cache");
-        if let Some(cached_video) = {
-            let cache = state.cache.read().await;
-            cache.get(video_id).cloned()
-        } {
-            debug!("Checking if {video_id} is fully cached");
-            if let Some(cached_video) = cached_video {
-                debug!("Checking if {video_id} is expired");
-                if cached_video.exp > SystemTime::now() {
-                    info!("Processed {video_url}, redirecting...");
-                    return Ok(Redirect::temporary(cached_video.url));
-                }
-
-                info!("{video_id} is expired, removing...");
-                state.cache.write().await.remove(video_id);
+    debug!("Checking if {key} is in the cache");
+    let start = Instant::now();
+    let cached_video = state.cache.get(&key).await;
+    state.metrics.stage("lookup", start.elapsed());
+    if let Some(cached_video) = cached_video {
+        debug!("Checking if {key} is expired");
+        if cached_video.exp > SystemTime::now() {
+            if let Some(reason) = cached_video.unavailable {
+                info!("{key} is cached as unavailable ({reason})");
+                state.metrics.cache_lookup("unavailable");
+                return Err(unavailable(&reason));
             }
 
-            info!("{video_id} is being cached, waiting...");
-            time::sleep(Duration::from_millis(500)).await;
-            continue;
+            state.metrics.cache_lookup("hit");
+            state.cache.hit(&key).await;
+            return respond(state, req, &video_url, cached_video, cover, offset).await;
         }
 
-        info!("{video_id} is not cached, caching...");
-        state.cache.write().await.insert(video_id.to_string(), None);
+        info!("{key} is expired, removing...");
+        state.metrics.cache_lookup("expired");
+        state.cache.remove(&key).await;
+    } else {
+        state.metrics.cache_lookup("miss");
+    }
 
-        debug!("Attempting to get single video with yt-dlp");
-        let Ok(single_video) = get_single_video(&state.youtube_dl_path, &video_url, true) else {
-            state.cache.write().await.remove(video_id);
-            return Err("Unable to proxy video with yt-dlp");
-        };
+    // Only the first request for a video id runs this, the rest wait for its result
+    let start = Instant::now();
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    let cached_video = state
+        .flights
+        .run(
+            &key,
+            flight_timeout,
+            lead(state, &mut catalog, &key, video_id, format),
+        )
+        .await;
+    state.metrics.stage("resolve", start.elapsed());
+    let cached_video = cached_video?;
 
-        debug!("Attempting to get format url with yt-dlp");
-        let Ok(redirect_url) = get_format_url(&single_video) else {
-            state.cache.write().await.remove(video_id);
-            return Err("Unable to proxy video with yt-dlp");
-        };
+    // Another instance may have cached the video as unavailable
+    if let Some(reason) = cached_video.unavailable {
+        return Err(unavailable(&reason));
+    }
+
+    respond(state, req, &video_url, cached_video, cover, offset).await
+}
+
+/// Claim, resolve, and catalog a video by its id as the leader of its flight
+#[cfg_attr(
+    not(feature = "database"),
+    allow(unused_variables, clippy::needless_pass_by_ref_mut)
+)]
+pub async fn lead(
+    state: &RocketState,
+    catalog: &mut Catalog,
+    key: &str,
+    video_id: &str,
+    format: Format,
+) -> Result<CachedVideo, ProxyError> {
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    debug!("Attempting to claim {key} for extraction");
+    match claim(state.cache.as_ref(), key, flight_timeout).await {
+        None => return Err(ProxyError::Timeout),
+        Some(Claim::Cached(cached_video)) => {
+            info!("{key} was cached by another instance");
+            return Ok(cached_video);
+        }
+        Some(Claim::Locked) => {}
+    }
+
+    info!("{key} is not cached, caching...");
+    let video_url = format!("https://youtu.be/{video_id}");
+    let result = resolve(state, key, &video_url, format).await;
+    state.cache.unlock(key).await;
+
+    #[cfg(feature = "database")]
+    if let Some(conn) = catalog.conn.as_mut() {
+        self::catalog(conn, state, video_id, &result).await;
+    }
 
-        debug!("Attempting to capture expiration from redirect url with regex");
-        let mut exp = SystemTime::now() + Duration::from_secs(600);
-        if let Some(captures) = state.expire_regex.captures(&redirect_url) {
-            debug!("Attempting to get expiration from capture");
-            if let Some(expiration) = captures.get(1) {
-                debug!("Attempting to parse expiration into seconds");
-                if let Ok(secs) = expiration.as_str().parse::<u64>() {
-                    debug!("Captured and parsed expiration {secs}");
-                    exp = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    result.map(|(cached_video, _)| cached_video)
+}
+
+/// Save a resolved video and its channel to the database, or flag the video if it's unavailable
+#[cfg(feature = "database")]
+async fn catalog(
+    conn: &mut Connection<VRChatYouTube>,
+    state: &RocketState,
+    video_id: &str,
+    result: &Result<(CachedVideo, Box<SingleVideo>), ProxyError>,
+) {
+    let single_video = match result {
+        Ok((_, single_video)) => single_video,
+        Err(ProxyError::Youtube(error)) => {
+            // Live streams that haven't started will become available, so don't flag them
+            if let Some(reason) = error.unavailable_reason() {
+                if !matches!(**error, YoutubeError::LiveNotStarted) {
+                    let video_id = video_id.to_string();
+                    let reason = reason.to_string();
+                    if let Err(error) = set_video_unavailable(conn, video_id, reason).await {
+                        eprintln!("Error flagging video as unavailable: {error}");
+                        state.metrics.database_error("set_video_unavailable");
+                    }
                 }
             }
+
+            return;
         }
+        Err(_) => return,
+    };
 
-        debug!("Updating cache with redirect url");
-        state.cache.write().await.insert(
-            video_id.to_string(),
-            Some(CachedVideo {
-                exp,
-                url: redirect_url.clone(),
-            }),
-        );
+    // common SQLx version must match rocket_db_pools SQLx version
+    if let Ok(channel) = Channel::try_from(*single_video.clone()) {
+        if let Err(error) = insert_channel(conn, channel).await {
+            eprintln!("Error inserting channel: {error}");
+            state.metrics.database_error("insert_channel");
+        }
+    }
+    if let Ok(video) = Video::try_from(*single_video.clone()) {
+        if let Err(error) = upsert_video(conn, video).await {
+            eprintln!("Error upserting video: {error}");
+            state.metrics.database_error("upsert_video");
+        }
+    }
+}
 
-        #[cfg(feature = "database")]
-        {
-            // common SQLx version must match rocket_db_pools SQLx version
-            if let Ok(channel) = Channel::try_from(*single_video.clone()) {
-                if let Err(error) = insert_channel(&mut conn, channel).await {
-                    eprintln!("Error inserting channel: {error}");
-                }
-            };
-            if let Ok(video) = Video::try_from(*single_video.clone()) {
-                if let Err(error) = upsert_video(&mut conn, video).await {
-                    eprintln!("Error upserting video: {error}");
+/// Play a link to another site on the allowlist, keyed by its extractor and id once yt-dlp has seen it
+async fn generic(
+    req: &Request<'_>,
+    state: &RocketState,
+    request_uri: &str,
+    audio_only: bool,
+) -> Result<Stream, ProxyError> {
+    debug!("Attempting to check link against the allowed hosts");
+    let Some(video_url) = url::allowed_url(request_uri, &state.config.generic_hosts) else {
+        return Err(ProxyError::Link);
+    };
+
+    let format = select_format(req, state, audio_only).await?;
+    let cover = wants_cover(req, format);
+    // Different links can point at the same video, so the link only leads to its key
+    let alias = cache_key(&video_url, format);
+    info!("Processing {video_url}...");
+
+    // Links are cached as they were asked for too, so unavailable links and other instances find them
+    let key = state.aliases.lock().await.get(&alias).cloned();
+    for key in iter::once(&alias).chain(&key) {
+        debug!("Checking if {key} is in the cache");
+        let Some(cached_video) = state.cache.get(key).await else {
+            continue;
+        };
+
+        if cached_video.exp > SystemTime::now() {
+            if let Some(reason) = cached_video.unavailable {
+                info!("{key} is cached as unavailable ({reason})");
+                state.metrics.cache_lookup("unavailable");
+                return Err(unavailable(&reason));
+            }
+
+            state.metrics.cache_lookup("hit");
+            state.cache.hit(key).await;
+            return respond(state, req, &video_url, cached_video, cover, None).await;
+        }
+    }
+    state.metrics.cache_lookup("miss");
+
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    let cached_video = state
+        .flights
+        .run(&alias, flight_timeout, async {
+            debug!("Attempting to claim {alias} for extraction");
+            match claim(state.cache.as_ref(), &alias, flight_timeout).await {
+                None => return Err(ProxyError::Timeout),
+                Some(Claim::Cached(cached_video)) => {
+                    info!("{alias} was cached by another instance");
+                    return Ok(cached_video);
                 }
+                Some(Claim::Locked) => {}
             }
+
+            let result = async {
+                let single_video = extract(state, Some(&alias), &video_url, format).await?;
+                let Some(extractor) = single_video
+                    .extractor_key
+                    .as_deref()
+                    .or(single_video.extractor.as_deref())
+                else {
+                    return Err(ProxyError::VideoId);
+                };
+
+                let video_id = format!("{}:{}", extractor.to_lowercase(), single_video.id);
+                let key = cache_key(&video_id, format);
+                info!("{video_url} is {key}, caching...");
+                state.aliases.lock().await.put(alias.clone(), key.clone());
+
+                let cached_video = store(state, &key, &video_url, format, &single_video).await?;
+                state
+                    .cache
+                    .insert(alias.clone(), cached_video.clone())
+                    .await;
+                Ok(cached_video)
+            }
+            .await;
+            state.cache.unlock(&alias).await;
+
+            result
+        })
+        .await?;
+
+    // Another instance may have cached the link as unavailable
+    if let Some(reason) = cached_video.unavailable {
+        return Err(unavailable(&reason));
+    }
+
+    respond(state, req, &video_url, cached_video, cover, None).await
+}
+
+/// Whether an audio only request wants the thumbnail shown, for video players
+fn wants_cover(req: &Request<'_>, format: Format) -> bool {
+    format.audio
+        && matches!(
+            req.query_value::<&str>("cover").and_then(Result::ok),
+            Some("1" | "true")
+        )
+}
+
+/// Format profile of the request, from the `/audio` prefix, query parameters, or platform in that order
+async fn select_format(
+    req: &Request<'_>,
+    state: &RocketState,
+    audio_only: bool,
+) -> Result<Format, ProxyError> {
+    debug!("Attempting to parse format profile from query parameters");
+    let query = |name| req.query_value::<&str>(name).and_then(Result::ok);
+    let (res, codec, audio) = (query("res"), query("codec"), query("audio"));
+    let format = if audio_only {
+        Format {
+            audio: true,
+            ..Format::default()
         }
+    } else if res.is_none() && codec.is_none() && audio.is_none() {
+        let platform = req.guard::<Platform>().await.unwrap();
+        debug!("Using the format profile of {platform:?}");
+        state.config.platform_formats.get(platform)
+    } else {
+        Format::from_query(res, codec, audio)?
+    };
+
+    Ok(servable(state, format))
+}
+
+/// Format profile as it's cached and served, resolutions above 720p need muxing with ffmpeg
+pub fn servable(state: &RocketState, mut format: Format) -> Format {
+    if format.is_dash() && !(state.config.mux && state.ffmpeg_path.is_some()) {
+        debug!("Muxing is unavailable, limiting resolution to {PROGRESSIVE_MAX_RES}p");
+        format.res = Some(PROGRESSIVE_MAX_RES);
+    }
+
+    format
+}
+
+/// Transcode, mux, redirect to, or stream through the resolved url, depending on the video and stream mode
+async fn respond(
+    state: &RocketState,
+    req: &Request<'_>,
+    video_url: &str,
+    cached_video: CachedVideo,
+    cover: bool,
+    offset: Option<u64>,
+) -> Result<Stream, ProxyError> {
+    let start = Instant::now();
+    let result = stream(state, req, video_url, cached_video, cover, offset).await;
+    state.metrics.stage("respond", start.elapsed());
+
+    result
+}
+
+async fn stream(
+    state: &RocketState,
+    req: &Request<'_>,
+    video_url: &str,
+    cached_video: CachedVideo,
+    cover: bool,
+    offset: Option<u64>,
+) -> Result<Stream, ProxyError> {
+    let CachedVideo {
+        url,
+        audio_url,
+        transcode,
+        live,
+        thumbnail,
+        ..
+    } = cached_video;
+
+    // The cover shares the transcode limit, so it falls back to the plain audio when transcoding is off
+    let cover = cover && state.config.max_transcodes > 0;
+    if let (true, Some(thumbnail), Some(ffmpeg_path)) = (cover, thumbnail, &state.ffmpeg_path) {
+        debug!("Attempting to acquire a free transcode");
+        let Ok(permit) = state.transcodes.clone().try_acquire_owned() else {
+            return Err(ProxyError::Busy(state.config.retry_after));
+        };
+
+        debug!("Attempting to mux audio with thumbnail with ffmpeg");
+        let stream = ffmpeg::cover(ffmpeg_path, &thumbnail, &url, permit).map_err(|error| {
+            warn!("Unable to mux {video_url} with its thumbnail with ffmpeg: {error}");
+            ProxyError::Ffmpeg
+        })?;
+
+        info!("Processed {video_url}, muxing with thumbnail...");
+        return Ok(Stream::Ffmpeg(stream));
+    }
+
+    if let (true, Some(ffmpeg_path)) = (transcode, &state.ffmpeg_path) {
+        debug!("Attempting to acquire a free transcode");
+        let Ok(permit) = state.transcodes.clone().try_acquire_owned() else {
+            return Err(ProxyError::Busy(state.config.retry_after));
+        };
+
+        debug!("Attempting to transcode video with ffmpeg");
+        let stream = ffmpeg::transcode(ffmpeg_path, &url, audio_url.as_deref(), permit).map_err(
+            |error| {
+                warn!("Unable to transcode {video_url} with ffmpeg: {error}");
+                ProxyError::Ffmpeg
+            },
+        )?;
+
+        info!("Processed {video_url}, transcoding...");
+        return Ok(Stream::Ffmpeg(stream));
+    }
+
+    if let (Some(audio_url), Some(ffmpeg_path)) = (audio_url, &state.ffmpeg_path) {
+        debug!("Attempting to acquire a free mux");
+        let Ok(permit) = state.muxes.clone().try_acquire_owned() else {
+            return Err(ProxyError::Busy(state.config.retry_after));
+        };
+
+        debug!("Attempting to mux video and audio with ffmpeg");
+        let stream = ffmpeg::mux(ffmpeg_path, &url, &audio_url, permit).map_err(|error| {
+            warn!("Unable to mux {video_url} with ffmpeg: {error}");
+            ProxyError::Ffmpeg
+        })?;
+
+        info!("Processed {video_url}, muxing...");
+        return Ok(Stream::Ffmpeg(stream));
+    }
+
+    let passthrough = !live
+        && match state.config.stream_mode {
+            StreamMode::Auto => is_ip_pinned(&url),
+            StreamMode::Redirect => false,
+            StreamMode::Passthrough => true,
+        };
+
+    if !passthrough {
+        // Players that support media fragments start at the offset of the link, the rest ignore it
+        let url = match offset {
+            Some(offset) if !live => format!("{url}#t={offset}"),
+            _ => url,
+        };
 
         info!("Processed {video_url}, redirecting...");
-        return Ok(Redirect::temporary(redirect_url));
+        return Ok(Stream::Redirect(Redirect::temporary(url)));
+    }
+
+    debug!("Attempting to request stream url");
+    let mut request = state.http.get(url);
+    if let Some(range) = req.headers().get_one("Range") {
+        request = request.header("Range", range);
+    }
+
+    let upstream = request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|error| {
+            warn!("Unable to stream {video_url}: {error}");
+            ProxyError::Upstream
+        })?;
+
+    info!("Processed {video_url}, streaming...");
+    Ok(Stream::Passthrough(Passthrough(upstream)))
+}
+
+/// Cache key of a video id and format profile, the default profile is keyed by the video id alone
+#[must_use]
+pub fn cache_key(video_id: &str, format: Format) -> String {
+    if format == Format::default() {
+        video_id.to_string()
+    } else {
+        format!("{video_id}?{format}")
+    }
+}
+
+/// Inverse of [`cache_key`]
+pub fn parse_cache_key(key: &str) -> Result<(&str, Format), YoutubeError> {
+    match key.split_once('?') {
+        Some((video_id, query)) => Ok((video_id, query.parse()?)),
+        None => Ok((key, Format::default())),
     }
 }
+
+/// Extract the stream url of a video with yt-dlp and cache it under `key`
+pub async fn resolve(
+    state: &RocketState,
+    key: &str,
+    video_url: &str,
+    format: Format,
+) -> Result<(CachedVideo, Box<SingleVideo>), ProxyError> {
+    let single_video = extract(state, Some(key), video_url, format).await?;
+    let cached_video = store(state, key, video_url, format, &single_video).await?;
+    remember_metadata(state, &single_video).await;
+
+    Ok((cached_video, single_video))
+}
+
+/// Get a video with yt-dlp, caching why it's unavailable under `key` if there is one
+async fn extract(
+    state: &RocketState,
+    key: Option<&str>,
+    video_url: &str,
+    format: Format,
+) -> Result<Box<SingleVideo>, ProxyError> {
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    debug!("Attempting to get single video with yt-dlp");
+    let start = Instant::now();
+    let result = get_single_video_async(&state.youtube_dl_path, video_url, true, format).await;
+    drop(permit);
+
+    state.metrics.extraction(&result, start.elapsed());
+
+    let single_video = match result {
+        Ok(single_video) => single_video,
+        Err(error) => {
+            warn!("Unable to get {video_url} with yt-dlp: {error}");
+            if let (Some(key), Some(ttl)) = (key, state.config.negative_ttl.get(&error)) {
+                debug!("Caching {key} as unavailable for {ttl} seconds");
+                let cached_video = CachedVideo {
+                    exp:         SystemTime::now() + Duration::from_secs(ttl),
+                    url:         String::new(),
+                    hits:        0,
+                    audio_url:   None,
+                    transcode:   false,
+                    live:        false,
+                    thumbnail:   None,
+                    unavailable: error.unavailable_reason().map(String::from),
+                };
+
+                state.cache.insert(key.to_string(), cached_video).await;
+            }
+
+            return Err(error.into());
+        }
+    };
+
+    Ok(single_video)
+}
+
+/// Cache the stream url of an extracted video under `key`
+async fn store(
+    state: &RocketState,
+    key: &str,
+    video_url: &str,
+    format: Format,
+    single_video: &SingleVideo,
+) -> Result<CachedVideo, ProxyError> {
+    if single_video.is_live.unwrap_or_default() {
+        debug!("Attempting to get live manifest url with yt-dlp");
+        let manifest_url = get_live_url(single_video, format).inspect_err(|error| {
+            warn!("Unable to get live manifest url of {video_url}: {error}");
+        })?;
+
+        // Live manifest urls expire differently, so they're cached briefly regardless of their expiration
+        let cached_video = CachedVideo {
+            exp:         SystemTime::now() + Duration::from_secs(state.config.live_ttl),
+            url:         manifest_url,
+            hits:        0,
+            audio_url:   None,
+            transcode:   false,
+            live:        true,
+            thumbnail:   None,
+            unavailable: None,
+        };
+
+        debug!("Updating cache with live manifest url");
+        state
+            .cache
+            .insert(key.to_string(), cached_video.clone())
+            .await;
+
+        return Ok(cached_video);
+    }
+
+    debug!("Attempting to get format urls with yt-dlp");
+    let mut format_urls = get_format_urls(single_video)
+        .inspect_err(|error| warn!("Unable to get format url of {video_url}: {error}"))?
+        .into_iter();
+    let Some(redirect_url) = format_urls.next() else {
+        return Err(YoutubeError::VideoUrl.into());
+    };
+    let audio_url = format_urls.next();
+
+    // Only H.264 is transcoded to, other codecs are best effort
+    let transcode = state.config.max_transcodes > 0
+        && format.codec == Some(Codec::H264)
+        && !is_codec(single_video, Codec::H264);
+    if transcode {
+        info!("{video_url} has no H.264 format, it will be transcoded");
+    }
+
+    debug!("Attempting to parse expiration from format urls");
+    let exp = expiration(state, &redirect_url, audio_url.as_deref());
+
+    let cached_video = CachedVideo {
+        exp,
+        url: redirect_url,
+        hits: 0,
+        audio_url,
+        transcode,
+        live: false,
+        // Only audio is shown with its thumbnail
+        thumbnail: single_video.thumbnail.clone().filter(|_| format.audio),
+        unavailable: None,
+    };
+
+    debug!("Updating cache with redirect url");
+    state
+        .cache
+        .insert(key.to_string(), cached_video.clone())
+        .await;
+
+    Ok(cached_video)
+}
+
+/// When to drop format urls from the cache, the earliest of their expiries minus the margin
+fn expiration(state: &RocketState, video_url: &str, audio_url: Option<&str>) -> SystemTime {
+    let expiry = std::iter::once(video_url)
+        .chain(audio_url)
+        .filter_map(url::expiry)
+        .min();
+
+    let Some(secs) = expiry else {
+        debug!(
+            "Format urls have no expiration, caching for {}s",
+            state.config.fallback_ttl
+        );
+        return SystemTime::now() + Duration::from_secs(state.config.fallback_ttl);
+    };
+
+    debug!("Parsed expiration {secs}");
+    let secs = secs.saturating_sub(state.config.expiry_margin);
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Error of a video cached as unavailable, see [`YoutubeError::unavailable_reason`]
+pub fn unavailable(reason: &str) -> ProxyError {
+    YoutubeError::from_unavailable_reason(reason).map_or(ProxyError::YoutubeDL, ProxyError::from)
+}
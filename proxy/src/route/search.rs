@@ -0,0 +1,102 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use common::youtube_dl::{search_async, SingleVideo};
+use rocket::{http::uri::Origin, response::Redirect, serde::json::Value, State};
+
+use crate::{error::ProxyError, RocketState};
+
+/// Results searched for one passing the filters, the top result alone is often a live stream
+const SEARCH_RESULTS: usize = 5;
+
+#[derive(Clone)]
+pub struct CachedSearch {
+    exp:      SystemTime,
+    video_id: String,
+}
+
+/// Redirect to the top `YouTube` result of a text search, typing a song name is easier than a video id in VR
+#[get("/s/<query>")]
+pub async fn search(
+    query: &str,
+    uri: &Origin<'_>,
+    state: &State<Arc<RocketState>>,
+) -> Result<Redirect, ProxyError> {
+    debug!("Attempting to normalize search query");
+    let query = query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let max_length = state.config.search_max_length;
+    if query.is_empty() || query.chars().count() > max_length {
+        return Err(ProxyError::SearchQuery(max_length));
+    }
+
+    let video_id = get_video_id(state, &query).await?;
+
+    // Query parameters like `res` and `codec` are passed on to the video
+    let location = match uri.query() {
+        Some(params) => format!("/{video_id}?{params}"),
+        None => format!("/{video_id}"),
+    };
+
+    Ok(Redirect::temporary(location))
+}
+
+/// Id of the top search result passing the filters, from the cache or yt-dlp if it's missing or expired
+async fn get_video_id(state: &RocketState, query: &str) -> Result<String, ProxyError> {
+    debug!("Checking if search {query} is in the cache");
+    if let Some(cached_search) = state.searches.lock().await.get(query) {
+        if cached_search.exp > SystemTime::now() {
+            return Ok(cached_search.video_id.clone());
+        }
+    }
+
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    info!("Searching for {query}...");
+    let results = search_async(&state.youtube_dl_path, query, SEARCH_RESULTS)
+        .await
+        .inspect_err(|error| warn!("Unable to search for {query} with yt-dlp: {error}"))?;
+    drop(permit);
+
+    let Some(single_video) = results
+        .into_iter()
+        .find(|single_video| is_playable(state, single_video))
+    else {
+        info!("No results for {query}");
+        return Err(ProxyError::NoResults);
+    };
+
+    debug!("Updating cache with search result");
+    let cached_search = CachedSearch {
+        exp:      SystemTime::now() + Duration::from_secs(state.config.search_ttl),
+        video_id: single_video.id.clone(),
+    };
+    state
+        .searches
+        .lock()
+        .await
+        .put(query.to_string(), cached_search);
+
+    info!("Found {} for {query}", single_video.id);
+    Ok(single_video.id)
+}
+
+/// Whether a search result isn't live and is within the duration cap
+fn is_playable(state: &RocketState, single_video: &SingleVideo) -> bool {
+    // Live streams and premieres don't have a duration yet
+    let Some(duration) = single_video.duration.as_ref().and_then(Value::as_f64) else {
+        return false;
+    };
+
+    let max_duration = state.config.search_max_duration;
+    !single_video.is_live.unwrap_or_default()
+        && (max_duration == 0 || duration <= f64::from(max_duration))
+}
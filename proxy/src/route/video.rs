@@ -0,0 +1,247 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+#[cfg(feature = "database")]
+use common::sqlx::{get_catalog_video, CatalogVideo};
+use common::{
+    url::is_video_id,
+    youtube_dl::{get_resolutions, get_single_video_async, Format, SingleVideo},
+};
+use rocket::{
+    serde::{
+        json::{Json, Value},
+        Serialize,
+    },
+    State,
+};
+
+use super::proxy::{cache_key, lead, servable, unavailable};
+use crate::{catalog::Catalog, error::ProxyError, CachedVideo, RocketState};
+
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct VideoMetadata {
+    id:          String,
+    title:       Option<String>,
+    channel:     Option<String>,
+    channel_id:  Option<String>,
+    /// Seconds, `None` for live streams and videos only known from the catalog
+    duration:    Option<f64>,
+    thumbnail:   Option<String>,
+    /// Values of `res` the video can be played at, empty for videos only known from the catalog
+    resolutions: Vec<u32>,
+    live:        bool,
+}
+
+impl From<&SingleVideo> for VideoMetadata {
+    fn from(single_video: &SingleVideo) -> Self {
+        Self {
+            id:          single_video.id.clone(),
+            title:       single_video.title.clone(),
+            channel:     single_video
+                .channel
+                .clone()
+                .or_else(|| single_video.uploader.clone()),
+            channel_id:  single_video.channel_id.clone(),
+            duration:    single_video.duration.as_ref().and_then(Value::as_f64),
+            thumbnail:   single_video.thumbnail.clone(),
+            resolutions: get_resolutions(single_video),
+            live:        single_video.is_live.unwrap_or_default(),
+        }
+    }
+}
+
+/// Catalog rows only have what the manager stores, the rest is filled in once the video is played
+#[cfg(feature = "database")]
+impl From<CatalogVideo> for VideoMetadata {
+    fn from(video: CatalogVideo) -> Self {
+        Self {
+            thumbnail:   Some(format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", video.id)),
+            id:          video.id,
+            title:       Some(video.title),
+            channel:     video.channel_name,
+            channel_id:  Some(video.channel_id),
+            duration:    None,
+            resolutions: Vec::new(),
+            live:        false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedMetadata {
+    exp:      SystemTime,
+    metadata: VideoMetadata,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct VideoResponse {
+    #[serde(flatten)]
+    metadata:   VideoMetadata,
+    /// Stream url of the default or a platform's format profile, if it's cached
+    stream_url: Option<String>,
+    /// Unix timestamp the stream url is cached until
+    stream_exp: Option<u64>,
+}
+
+/// Metadata of a video as JSON for world scripts and bots, without playing it
+#[get("/api/video/<video_id>")]
+pub async fn api_video(
+    video_id: &str,
+    mut catalog: Catalog,
+    state: &State<Arc<RocketState>>,
+) -> Result<Json<VideoResponse>, ProxyError> {
+    if !is_video_id(video_id) {
+        return Err(ProxyError::VideoId);
+    }
+
+    let cached_video = get_stream(state, video_id).await?;
+    let metadata = get_metadata(state, &mut catalog, video_id).await?;
+    // Getting the metadata may have resolved the video
+    let cached_video = match cached_video {
+        Some(cached_video) => Some(cached_video),
+        None => get_stream(state, video_id).await?,
+    };
+
+    let (stream_url, stream_exp) = cached_video.map_or((None, None), |cached_video| {
+        let exp = cached_video.exp.duration_since(SystemTime::UNIX_EPOCH);
+        (Some(cached_video.url), exp.ok().map(|exp| exp.as_secs()))
+    });
+
+    Ok(Json(VideoResponse {
+        metadata,
+        stream_url,
+        stream_exp,
+    }))
+}
+
+/// Keep the metadata of a video extracted for playing, so asking for it afterwards doesn't run yt-dlp again
+pub async fn remember_metadata(state: &RocketState, single_video: &SingleVideo) {
+    let cached_metadata = CachedMetadata {
+        exp:      SystemTime::now() + Duration::from_secs(state.config.metadata_ttl),
+        metadata: VideoMetadata::from(single_video),
+    };
+
+    state
+        .metadata
+        .lock()
+        .await
+        .put(single_video.id.clone(), cached_metadata);
+}
+
+/// Unexpired stream of a video under the default or any platform's format profile,
+/// players are sent their platform's profile so the default one may never be cached
+async fn get_stream(
+    state: &RocketState,
+    video_id: &str,
+) -> Result<Option<CachedVideo>, ProxyError> {
+    let platform_formats = &state.config.platform_formats;
+    let mut keys = Vec::new();
+    for format in [
+        Format::default(),
+        platform_formats.quest,
+        platform_formats.pc,
+        platform_formats.other,
+    ] {
+        let key = cache_key(video_id, servable(state, format));
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    for key in keys {
+        debug!("Checking if {key} is in the cache");
+        let Some(cached_video) = state.cache.get(&key).await else {
+            continue;
+        };
+
+        if cached_video.exp <= SystemTime::now() {
+            continue;
+        }
+
+        if let Some(reason) = &cached_video.unavailable {
+            return Err(unavailable(reason));
+        }
+
+        return Ok(Some(cached_video));
+    }
+
+    Ok(None)
+}
+
+/// Metadata of a video from the cache, the catalog, or yt-dlp in that order
+#[cfg_attr(
+    not(feature = "database"),
+    allow(unused_variables, clippy::needless_pass_by_ref_mut)
+)]
+async fn get_metadata(
+    state: &RocketState,
+    catalog: &mut Catalog,
+    video_id: &str,
+) -> Result<VideoMetadata, ProxyError> {
+    if let Some(metadata) = get_cached_metadata(state, video_id).await {
+        return Ok(metadata);
+    }
+
+    #[cfg(feature = "database")]
+    if let Some(conn) = catalog.conn.as_mut() {
+        debug!("Checking if {video_id} is in the catalog");
+        match get_catalog_video(conn, video_id).await {
+            Ok(Some(video)) => match video.unavailable.as_deref() {
+                Some(reason) => return Err(unavailable(reason)),
+                None => return Ok(VideoMetadata::from(video)),
+            },
+            Ok(None) => {}
+            Err(error) => {
+                error!("Error getting catalog video: {error}");
+                state.metrics.database_error("get_catalog_video");
+            }
+        }
+    }
+
+    // Resolving the default profile remembers the metadata, and shares yt-dlp with players of the same video
+    let key = cache_key(video_id, Format::default());
+    let flight_timeout = Duration::from_secs(state.config.flight_timeout);
+    state
+        .flights
+        .run(
+            &key,
+            flight_timeout,
+            lead(state, catalog, &key, video_id, Format::default()),
+        )
+        .await?;
+
+    if let Some(metadata) = get_cached_metadata(state, video_id).await {
+        return Ok(metadata);
+    }
+
+    // The flight was led by a player whose video was cached by another instance, so it never ran yt-dlp here
+    debug!("Waiting for a free yt-dlp process");
+    let Some(permit) = state.extractions.acquire().await else {
+        return Err(ProxyError::Busy(state.config.retry_after));
+    };
+
+    let video_url = format!("https://youtu.be/{video_id}");
+    debug!("Attempting to get single video with yt-dlp");
+    let start = Instant::now();
+    let result =
+        get_single_video_async(&state.youtube_dl_path, &video_url, true, Format::default()).await;
+    drop(permit);
+
+    state.metrics.extraction(&result, start.elapsed());
+    let single_video =
+        result.inspect_err(|error| warn!("Unable to get {video_url} with yt-dlp: {error}"))?;
+
+    remember_metadata(state, &single_video).await;
+    Ok(VideoMetadata::from(&*single_video))
+}
+
+async fn get_cached_metadata(state: &RocketState, video_id: &str) -> Option<VideoMetadata> {
+    debug!("Checking if metadata of {video_id} is in the cache");
+    let cached_metadata = state.metadata.lock().await.get(video_id).cloned()?;
+
+    (cached_metadata.exp > SystemTime::now()).then_some(cached_metadata.metadata)
+}
@@ -0,0 +1,102 @@
+use std::io;
+
+use rocket::{
+    futures::TryStreamExt,
+    http::Status,
+    response::{self, Redirect, Responder},
+    serde::Deserialize,
+    Request,
+    Response,
+};
+use tokio_util::io::StreamReader;
+
+use crate::ffmpeg::FfmpegStream;
+
+/// Headers copied from the upstream response so players can seek
+const PASSTHROUGH_HEADERS: [&str; 4] = [
+    "Accept-Ranges",
+    "Content-Length",
+    "Content-Range",
+    "Content-Type",
+];
+
+/// How a resolved stream url is sent to the client
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum StreamMode {
+    /// Redirect, unless the url is signed for the proxy's IP address
+    Auto,
+
+    /// Always redirect to the upstream url
+    #[default]
+    Redirect,
+
+    /// Always stream the upstream bytes through the proxy
+    Passthrough,
+}
+
+#[derive(Responder)]
+pub enum Stream {
+    Redirect(Redirect),
+    Passthrough(Passthrough),
+    Ffmpeg(FfmpegStream),
+}
+
+/// Upstream response streamed to the client as is
+pub struct Passthrough(pub reqwest::Response);
+
+impl<'r> Responder<'r, 'static> for Passthrough {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let upstream = self.0;
+        let mut response = Response::build();
+        response.status(Status::new(upstream.status().as_u16()));
+
+        for name in PASSTHROUGH_HEADERS {
+            if let Some(value) = upstream.headers().get(name) {
+                if let Ok(value) = value.to_str() {
+                    response.raw_header(name, value.to_owned());
+                }
+            }
+        }
+
+        let body = upstream.bytes_stream().map_err(io::Error::other);
+        response.streamed_body(StreamReader::new(body)).ok()
+    }
+}
+
+/// Whether the url is signed for the IP address that resolved it, as googlevideo urls are with `ip=`
+#[must_use]
+pub fn is_ip_pinned(url: &str) -> bool {
+    let Some((path, query)) = url.split_once('?') else {
+        return url.contains("/ip/");
+    };
+
+    path.contains("/ip/")
+        || query
+            .split('&')
+            .any(|param| param.split_once('=').is_some_and(|(key, _)| key == "ip"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_ip_pinned_urls() {
+        assert!(is_ip_pinned(
+            "https://rr1---sn-abc.googlevideo.com/videoplayback?expire=1700000000&ip=203.0.113.7&id=o-abc"
+        ));
+        assert!(is_ip_pinned("https://example.com/video.mp4?ip=2001:db8::1"));
+        assert!(is_ip_pinned(
+            "https://manifest.googlevideo.com/api/manifest/hls_playlist/expire/1700000000/ip/203.0.113.7/id/abc/index.m3u8"
+        ));
+        assert!(is_ip_pinned("https://example.com/ip/203.0.113.7/video.mp4"));
+
+        assert!(!is_ip_pinned("https://example.com/video.mp4"));
+        assert!(!is_ip_pinned("https://example.com/video.mp4?clip=1&zip=2"));
+        assert!(!is_ip_pinned(
+            "https://example.com/video.mp4?ipbits=0&sip=1"
+        ));
+        assert!(!is_ip_pinned("https://example.com/videos/ip.mp4"));
+    }
+}